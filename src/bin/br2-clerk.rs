@@ -6,20 +6,34 @@
 // SPDX-License-Identifier: MIT
 //
 
-use anyhow::{Context, Result};
-use br2_utils::BuildrootExplorer;
+use anyhow::{anyhow, Context as _, Result};
+use br2_utils::{builder::BuildFailure, Buildroot, BuildrootExplorer, TreeRef};
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use output::Format;
+use std::{path::PathBuf, process::ExitCode};
+use topics::board::Board;
 use topics::build::Build;
+use topics::cache::Cache;
 use topics::defconfig::Defconfig;
+use topics::external::External;
+use topics::info::Info;
 use topics::package::Package;
 
 #[derive(Debug, Subcommand)]
 enum Topic {
+    #[clap(visible_aliases = ["bd", "brd"])]
+    Board(Board),
     #[clap(visible_aliases = ["b", "bld"])]
     Build(Build),
+    #[clap(visible_alias = "c")]
+    Cache(Cache),
     #[clap(visible_aliases = ["d", "def"])]
     Defconfig(Defconfig),
+    #[clap(visible_aliases = ["e", "ext"])]
+    External(External),
+    /// Show information about the environment
+    #[clap(visible_alias = "i")]
+    Info(Info),
     #[clap(visible_aliases = ["p", "pkg"])]
     Package(Package),
 }
@@ -31,35 +45,204 @@ enum Topic {
     about = "Provide information or perform tasks on Buildroot environment"
 )]
 struct Cli {
-    #[arg(short, long, help = "Path to main tree")]
+    #[arg(
+        short,
+        long,
+        help = "Path to main tree [default: current directory, with external trees from BR2_EXTERNAL]"
+    )]
     main: Option<PathBuf>,
+    #[arg(
+        short = 'O',
+        long,
+        conflicts_with = "main",
+        help = "Path to output directory to get trees from"
+    )]
+    output: Option<PathBuf>,
     #[arg(short, long, help = "Path to external tree")]
     externals: Vec<PathBuf>,
+    #[arg(long, help = "Do not use the cached index of the trees")]
+    no_cache: bool,
+    #[arg(
+        short,
+        long,
+        help = "Restrict listings to the tree named NAME",
+        value_name = "NAME"
+    )]
+    tree: Option<String>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_enum,
+        default_value_t = Format::Text,
+        help = "Output format"
+    )]
+    format: Format,
     #[command(subcommand, help = "Topic to handle")]
     topic: Topic,
 }
-pub fn main() -> Result<()> {
+
+/// Environment and options shared by all topics.
+pub struct Context<'a> {
+    pub buildroot: &'a Buildroot,
+    pub tree: Option<TreeRef<'a>>,
+    pub format: Format,
+}
+
+impl<'a> Context<'a> {
+    /// Check if the defconfig named `name` belongs to the selected tree, if any.
+    pub fn has_defconfig(&self, name: &str) -> bool {
+        self.tree.is_none_or(|t| t.has_defconfig(name))
+    }
+
+    /// Check if the package named `name` belongs to the selected tree, if any.
+    pub fn has_package(&self, name: &str) -> bool {
+        self.tree.is_none_or(|t| t.has_package(name))
+    }
+}
+
+pub fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => BuildFailure::report(e.as_ref(), false),
+    }
+}
+
+fn run() -> Result<()> {
     let args = Cli::parse();
-    let cur_dir = std::env::current_dir().with_context(|| "Failed to get current directory")?;
-    let path = args.main.unwrap_or(cur_dir);
-    let mut explorer = BuildrootExplorer::new(path);
+    let cache = utils::user_cache_dir().map(br2_utils::cache::Cache::new);
+    if let Topic::Cache(ref topic) = args.topic {
+        let cache = cache.ok_or(anyhow!("No cache directory found"))?;
+        topic
+            .execute(&cache)
+            .with_context(|| "Failed to handle cache")?;
+        return Ok(());
+    }
+    let mut explorer = if let Some(ref output) = args.output {
+        BuildrootExplorer::from_output_dir(output)
+            .with_context(|| format!("Failed to get trees from {}", output.display()))?
+    } else if let Some(ref main) = args.main {
+        BuildrootExplorer::new(main)
+    } else {
+        BuildrootExplorer::from_env().with_context(|| "Failed to get trees from environment")?
+    };
     for path in &args.externals {
         explorer.external_tree(path);
     }
+    if let (Some(cache), false) = (cache, args.no_cache) {
+        explorer.cache(cache);
+    }
     let buildroot = explorer
         .explore()
         .with_context(|| "Failed to explore environment")?;
+    let tree = match args.tree {
+        Some(ref name) => Some(
+            buildroot
+                .tree(name)
+                .ok_or_else(|| anyhow!("Unknown tree: {name}"))?,
+        ),
+        None => None,
+    };
+    let ctx = Context {
+        buildroot: &buildroot,
+        tree,
+        format: args.format,
+    };
     match args.topic {
-        Topic::Build(ref topic) => topic.execute(&buildroot)?,
-        Topic::Defconfig(ref topic) => topic.execute(&buildroot)?,
-        Topic::Package(ref topic) => topic.execute(&buildroot)?,
+        Topic::Board(ref topic) => topic.execute(&ctx)?,
+        Topic::Build(ref topic) => topic.execute(&ctx)?,
+        Topic::Cache(_) => unreachable!(),
+        Topic::Defconfig(ref topic) => topic.execute(&ctx)?,
+        Topic::External(ref topic) => topic.execute(&ctx)?,
+        Topic::Info(ref topic) => topic.execute(&ctx)?,
+        Topic::Package(ref topic) => topic.execute(&ctx)?,
     }
     Ok(())
 }
 
 mod topics {
+    pub mod board {
+        use crate::{output, Context};
+        use anyhow::Result;
+        use clap::{Args, Subcommand};
+        use serde::Serialize;
+        use std::path::PathBuf;
+
+        #[derive(Debug, Serialize)]
+        struct BoardRecord<'a> {
+            defconfig: &'a str,
+            directory: Option<&'a PathBuf>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct MissingRecord<'a> {
+            defconfig: &'a str,
+            symbol: &'a str,
+            path: &'a PathBuf,
+        }
+
+        #[derive(Debug, Args)]
+        struct ListArgs {
+            #[arg(short, long, help = "Only show referenced files which are missing")]
+            missing: bool,
+        }
+
+        #[derive(Debug, Subcommand)]
+        enum BoardCommand {
+            /// List board directories and files referenced by defconfigs
+            #[clap(visible_alias = "ls")]
+            List(ListArgs),
+        }
+
+        #[derive(Debug, Args)]
+        pub struct Board {
+            #[command(subcommand)]
+            command: BoardCommand,
+        }
+
+        impl Board {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
+                match self.command {
+                    BoardCommand::List(ref args) => {
+                        let boards = ctx.buildroot.boards()?;
+                        let boards = boards.iter().filter(|b| ctx.has_defconfig(&b.defconfig));
+                        if args.missing {
+                            let records: Vec<MissingRecord> = boards
+                                .flat_map(|b| {
+                                    b.missing().map(|f| MissingRecord {
+                                        defconfig: &b.defconfig,
+                                        symbol: &f.symbol,
+                                        path: &f.path,
+                                    })
+                                })
+                                .collect();
+                            output::print_records(ctx.format, "missing", &records, |r| {
+                                format!("{:<32} {:<40} {}", r.defconfig, r.symbol, r.path.display())
+                            })
+                        } else {
+                            let records: Vec<BoardRecord> = boards
+                                .map(|b| BoardRecord {
+                                    defconfig: &b.defconfig,
+                                    directory: b.directory.as_ref(),
+                                })
+                                .collect();
+                            output::print_records(ctx.format, "boards", &records, |r| {
+                                let directory = r
+                                    .directory
+                                    .map(|d| d.display().to_string())
+                                    .unwrap_or("-".to_string());
+                                format!("{:<32} {}", r.defconfig, directory)
+                            })
+                        }
+                    }
+                }
+            }
+        }
+    }
     pub mod build {
-        use br2_utils::{builder::BuildStep, Buildroot, Error};
+        use crate::Context;
+        use anyhow::Result;
+        use br2_utils::builder::BuildStep;
         use clap::{Args, Subcommand};
         use std::path::PathBuf;
 
@@ -67,6 +250,8 @@ mod topics {
         struct RunArgs {
             #[arg(short, long, help = "Build step", default_value_t = BuildStep::All)]
             step: BuildStep,
+            #[arg(long, help = "Print the make commands instead of running them")]
+            dry_run: bool,
             #[arg(help = "Name of the defconfig")]
             name: String,
             #[arg(help = "Path to output directory")]
@@ -87,19 +272,61 @@ mod topics {
         }
 
         impl Build {
-            pub fn execute(&self, buildroot: &Buildroot) -> Result<(), Error> {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
                 match self.command {
+                    BuildCommand::Run(ref args) if args.dry_run => {
+                        let builder = ctx.buildroot.create_builder(&args.name, &args.output)?;
+                        for line in builder.step_dry_run_script(args.step)? {
+                            println!("{line}");
+                        }
+                        Ok(())
+                    }
                     BuildCommand::Run(ref args) => {
-                        buildroot.build(&args.name, &args.output, args.step)
+                        ctx.buildroot.build(&args.name, &args.output, args.step)?;
+                        Ok(())
                     }
                 }
             }
         }
     }
+    pub mod cache {
+        use br2_utils::cache::{Cache as IndexCache, Error};
+        use clap::{Args, Subcommand};
+
+        #[derive(Debug, Subcommand)]
+        enum CacheCommand {
+            /// Remove the cached index of all trees
+            Clear,
+        }
+
+        #[derive(Debug, Args)]
+        pub struct Cache {
+            #[command(subcommand)]
+            command: CacheCommand,
+        }
+
+        impl Cache {
+            pub fn execute(&self, cache: &IndexCache) -> Result<(), Error> {
+                match self.command {
+                    CacheCommand::Clear => cache.clear(),
+                }
+            }
+        }
+    }
     pub mod defconfig {
-        use br2_utils::{Buildroot, Error};
+        use crate::{output, output::Format, Context};
+        use anyhow::{Context as _, Result};
+        use br2_utils::legal;
         use clap::{Args, Subcommand};
-        use std::collections::BTreeSet;
+        use serde::Serialize;
+        use std::path::PathBuf;
+
+        #[derive(Debug, Serialize)]
+        struct DefconfigRecord<'a> {
+            name: &'a str,
+            tree: &'a str,
+            path: &'a PathBuf,
+        }
 
         #[derive(Debug, Args)]
         struct GetArgs {
@@ -109,11 +336,32 @@ mod topics {
             symbol: String,
         }
 
+        #[derive(Debug, Args)]
+        struct LicensesArgs {
+            #[arg(long, help = "Report host packages instead of target packages")]
+            host: bool,
+            #[arg(
+                short,
+                long,
+                help = "Resolved configuration to get all the enabled packages from, like $(O)/.config"
+            )]
+            config: Option<PathBuf>,
+            #[arg(
+                help = "Name of the defconfig",
+                required_unless_present = "config",
+                conflicts_with = "config"
+            )]
+            name: Option<String>,
+        }
+
         #[derive(Debug, Subcommand)]
         enum DefconfigCommand {
             /// Get value of a symbol
             #[clap(visible_alias = "g")]
             Get(GetArgs),
+            /// Report licenses of the packages enabled by a defconfig
+            #[clap(visible_alias = "lic")]
+            Licenses(LicensesArgs),
             /// List available defconfigs
             #[clap(visible_alias = "ls")]
             List,
@@ -126,10 +374,10 @@ mod topics {
         }
 
         impl Defconfig {
-            pub fn execute(&self, buildroot: &Buildroot) -> Result<(), Error> {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
                 match self.command {
                     DefconfigCommand::Get(ref args) => {
-                        let defconfig = buildroot.get_defconfig(&args.name)?;
+                        let defconfig = ctx.buildroot.get_defconfig(&args.name)?;
                         let symbol = defconfig
                             .symbols()
                             .iter()
@@ -137,31 +385,338 @@ mod topics {
                             .ok_or(br2_utils::defconfig::Error::InvalidSymbol(
                                 "unknown".to_string(),
                             ))?;
-                        println!("{}", symbol.value);
-                        Ok(())
+                        output::print_record(ctx.format, symbol, |s| s.value.to_string())
+                    }
+                    DefconfigCommand::Licenses(ref args) => {
+                        let report = match args.config {
+                            Some(ref path) => ctx.buildroot.config_licenses(path)?,
+                            None => {
+                                let name = args.name.as_deref().context("Missing defconfig")?;
+                                eprintln!(
+                                    "Warning: packages enabled by default or selected by other \
+                                     packages are missing, use --config for a complete report"
+                                );
+                                ctx.buildroot.licenses(name)?
+                            }
+                        };
+                        if !report.skipped.is_empty() {
+                            eprintln!(
+                                "Warning: packages without a version not reported: {}",
+                                report.skipped.join(" ")
+                            );
+                        }
+                        let entries: Vec<legal::LicenseEntry> = report
+                            .entries
+                            .into_iter()
+                            .filter(|e| e.is_host() == args.host)
+                            .collect();
+                        // Stay compatible with the manifest from "make legal-info".
+                        if let Format::Csv = ctx.format {
+                            return Ok(legal::write_manifest(&entries, std::io::stdout())?);
+                        }
+                        output::print_records(ctx.format, "licenses", &entries, |e| {
+                            let note = if e.redistribute {
+                                ""
+                            } else {
+                                " (not redistributable)"
+                            };
+                            format!("{:<32} {:<16} {}{note}", e.package, e.version, e.spdx)
+                        })
                     }
                     DefconfigCommand::List => {
-                        let items: BTreeSet<&String> =
-                            buildroot.defconfigs().map(|(n, _)| n).collect();
-                        for item in items {
-                            println!("{item}");
+                        let mut records: Vec<DefconfigRecord> = ctx
+                            .buildroot
+                            .trees()
+                            .flat_map(|t| {
+                                t.defconfigs().map(move |(n, p)| DefconfigRecord {
+                                    name: n,
+                                    tree: t.name(),
+                                    path: p,
+                                })
+                            })
+                            .filter(|r| ctx.has_defconfig(r.name))
+                            .collect();
+                        // Like Buildroot, use the first tree defining a defconfig.
+                        records.sort_by_key(|r| r.name);
+                        records.dedup_by_key(|r| r.name);
+                        output::print_records(ctx.format, "defconfigs", &records, |r| {
+                            r.name.to_string()
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    pub mod external {
+        use crate::{output, Context};
+        use anyhow::Result;
+        use br2_utils::shell::shell_quote;
+        use clap::{Args, Subcommand};
+        use serde::Serialize;
+        use std::path::{Path, PathBuf};
+
+        #[derive(Debug, Serialize)]
+        struct ExternalRecord<'a> {
+            name: &'a str,
+            description: &'a str,
+            path: &'a Path,
+            variable: String,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct OrphanRecord {
+            path: PathBuf,
+        }
+
+        #[derive(Debug, Args)]
+        struct OrphansArgs {
+            #[arg(short, long, help = "Output a shell script removing the files")]
+            script: bool,
+        }
+
+        #[derive(Debug, Subcommand)]
+        enum ExternalCommand {
+            /// List external trees
+            #[clap(visible_alias = "ls")]
+            List,
+            /// List files not referenced by any defconfig
+            #[clap(visible_alias = "o")]
+            Orphans(OrphansArgs),
+        }
+
+        #[derive(Debug, Args)]
+        pub struct External {
+            #[command(subcommand)]
+            command: ExternalCommand,
+        }
+
+        impl External {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
+                match self.command {
+                    ExternalCommand::List => {
+                        let records: Vec<ExternalRecord> = ctx
+                            .buildroot
+                            .externals()
+                            .filter(|e| ctx.tree.is_none_or(|t| t.name() == e.name()))
+                            .map(|e| ExternalRecord {
+                                name: e.name(),
+                                description: e.description(),
+                                path: e.path(),
+                                variable: e.path_variable(),
+                            })
+                            .collect();
+                        output::print_records(ctx.format, "externals", &records, |r| {
+                            format!("{:<24} {:<40} {}", r.name, r.path.display(), r.description)
+                        })
+                    }
+                    ExternalCommand::Orphans(ref args) => {
+                        let records: Vec<OrphanRecord> = ctx
+                            .buildroot
+                            .orphans()?
+                            .into_iter()
+                            .filter(|o| ctx.tree.is_none_or(|t| o.starts_with(t.path())))
+                            .map(|path| OrphanRecord { path })
+                            .collect();
+                        if args.script {
+                            println!("#!/bin/sh\nset -e");
+                            for record in records {
+                                let path = record.path.to_string_lossy();
+                                println!("rm -f -- {}", shell_quote(&path));
+                            }
+                            return Ok(());
                         }
-                        Ok(())
+                        output::print_records(ctx.format, "orphans", &records, |r| {
+                            r.path.display().to_string()
+                        })
                     }
                 }
             }
         }
     }
 
+    pub mod info {
+        use crate::{output, Context};
+        use anyhow::Result;
+        use br2_utils::version::BuildrootVersion;
+        use clap::Args;
+        use serde::Serialize;
+        use std::path::Path;
+
+        #[derive(Debug, Serialize)]
+        struct ExternalRecord<'a> {
+            name: &'a str,
+            path: &'a Path,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct InfoRecord<'a> {
+            main: &'a Path,
+            version: String,
+            lts: bool,
+            details: BuildrootVersion,
+            externals: Vec<ExternalRecord<'a>>,
+        }
+
+        #[derive(Debug, Args)]
+        pub struct Info;
+
+        impl Info {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
+                let version = ctx.buildroot.version()?;
+                let record = InfoRecord {
+                    main: ctx.buildroot.main_tree_path(),
+                    version: version.to_string(),
+                    lts: version.is_lts(),
+                    details: version,
+                    externals: ctx
+                        .buildroot
+                        .externals()
+                        .map(|e| ExternalRecord {
+                            name: e.name(),
+                            path: e.path(),
+                        })
+                        .collect(),
+                };
+                output::print_record(ctx.format, &record, |r| {
+                    let mut lines = vec![];
+                    let lts = if r.lts { " (LTS)" } else { "" };
+                    lines.push(format!("{:<16} {}", "Main tree:", r.main.display()));
+                    lines.push(format!("{:<16} {}{lts}", "Version:", r.version));
+                    if let Some(ref git) = r.details.git {
+                        let branch = git.branch.as_deref().unwrap_or("detached");
+                        lines.push(format!("{:<16} {} ({branch})", "Git:", git.describe()));
+                    }
+                    for external in &r.externals {
+                        lines.push(format!(
+                            "{:<16} {} {}",
+                            "External tree:",
+                            external.name,
+                            external.path.display()
+                        ));
+                    }
+                    lines.join("\n")
+                })
+            }
+        }
+    }
+
     pub mod package {
-        use br2_utils::{Buildroot, Error};
+        use crate::{output, output::Format, Context};
+        use anyhow::Result;
+        use br2_utils::{
+            defconfig::Defconfig,
+            package::{self, HashEntry, PackageInfo},
+            Error,
+        };
         use clap::{Args, Subcommand};
-        use std::collections::{BTreeMap, BTreeSet};
+        use regex::{Regex, RegexBuilder};
+        use serde::Serialize;
+        use std::{
+            collections::HashMap,
+            path::{Path, PathBuf},
+            sync::Arc,
+        };
+
+        enum Matcher {
+            Regex(Regex),
+            Fuzzy(String),
+        }
+
+        impl Matcher {
+            fn is_match(&self, text: &str) -> bool {
+                match self {
+                    Matcher::Regex(regex) => regex.is_match(text),
+                    Matcher::Fuzzy(pattern) => {
+                        // Characters of the pattern must appear in order.
+                        let mut chars = text.chars().flat_map(char::to_lowercase);
+                        pattern.chars().all(|p| chars.any(|c| c == p))
+                    }
+                }
+            }
+        }
+
+        #[derive(Debug, Serialize)]
+        struct PackageRecord<'a> {
+            name: &'a str,
+            tree: &'a str,
+            path: &'a PathBuf,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            version: Option<String>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct MatchRecord<'a> {
+            name: &'a str,
+            tree: &'a str,
+            prompt: Option<String>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct DetailsRecord<'a> {
+            name: &'a str,
+            tree: &'a str,
+            path: &'a Path,
+            version: Option<&'a str>,
+            site: Option<String>,
+            site_method: Option<String>,
+            source: Option<String>,
+            license: Option<String>,
+            license_files: Option<String>,
+            dependencies: Vec<String>,
+            infrastructures: &'a [String],
+            patches: Vec<PathBuf>,
+            hashes: Vec<HashEntry>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct OriginRecord<'a> {
+            name: &'a str,
+            tree: &'a str,
+            path: &'a Path,
+            shadowed: bool,
+        }
 
         #[derive(Debug, Args)]
         struct ListArgs {
             #[arg(short, long, help = "Show details")]
             details: bool,
+            #[arg(long, help = "Only show packages defined in several trees")]
+            duplicates: bool,
+        }
+
+        #[derive(Debug, Args)]
+        struct SearchArgs {
+            #[arg(
+                long,
+                help = "Match characters of the pattern in order, instead of a regular expression"
+            )]
+            fuzzy: bool,
+            #[arg(long, help = "Only show packages using the infrastructure NAME")]
+            infra: Option<String>,
+            #[arg(long, help = "Only show packages whose license contains TEXT")]
+            license: Option<String>,
+            #[arg(
+                long,
+                help = "Only show packages set in the defconfig NAME, not those enabled by default or selected by other packages",
+                value_name = "NAME"
+            )]
+            selected_by: Option<String>,
+            #[arg(
+                short,
+                long,
+                help = "Only show packages enabled in the resolved configuration, like $(O)/.config",
+                conflicts_with = "selected_by"
+            )]
+            config: Option<PathBuf>,
+            #[arg(help = "Pattern to match against names, prompts and help texts")]
+            pattern: String,
+        }
+
+        #[derive(Debug, Args)]
+        struct ShowArgs {
+            #[arg(help = "Name of the package")]
+            name: String,
         }
 
         #[derive(Debug, Args)]
@@ -177,6 +732,12 @@ mod topics {
             /// List available packages
             #[clap(visible_alias = "ls")]
             List(ListArgs),
+            /// Search packages by name, prompt or help text
+            #[clap(visible_alias = "s")]
+            Search(SearchArgs),
+            /// Show details of a package
+            #[clap(visible_alias = "sh")]
+            Show(ShowArgs),
             /// Change version of a package
             #[clap(visible_alias = "b")]
             Bump(BumpArgs),
@@ -189,35 +750,422 @@ mod topics {
         }
 
         impl Package {
-            pub fn execute(&self, buildroot: &Buildroot) -> Result<(), Error> {
+            pub fn execute(&self, ctx: &Context) -> Result<()> {
                 match self.command {
+                    PackageCommand::List(ref args) if args.duplicates => {
+                        let duplicates = ctx.buildroot.duplicate_packages();
+                        let duplicates = duplicates.iter().filter(|(n, _)| ctx.has_package(n));
+                        if let Format::Text = ctx.format {
+                            for (n, origins) in duplicates {
+                                let trees = origins
+                                    .iter()
+                                    .map(|o| {
+                                        if o.shadowed {
+                                            format!("({})", o.tree)
+                                        } else {
+                                            o.tree.to_string()
+                                        }
+                                    })
+                                    .collect::<Vec<String>>()
+                                    .join(" ");
+                                println!("{n:<32} {trees}");
+                            }
+                            return Ok(());
+                        }
+                        let records: Vec<OriginRecord> = duplicates
+                            .flat_map(|(n, origins)| {
+                                origins.iter().map(|o| OriginRecord {
+                                    name: n,
+                                    tree: o.tree,
+                                    path: o.path,
+                                    shadowed: o.shadowed,
+                                })
+                            })
+                            .collect();
+                        if let Some(s) =
+                            output::serialize_records(ctx.format, "duplicates", &records)?
+                        {
+                            print!("{s}");
+                        }
+                        Ok(())
+                    }
                     PackageCommand::List(ref args) => {
-                        let pkg_names = buildroot.packages().map(|(n, _)| n);
+                        let mut records: Vec<PackageRecord> = ctx
+                            .buildroot
+                            .packages()
+                            .filter(|(n, _)| ctx.has_package(n))
+                            .map(|(n, p)| PackageRecord {
+                                name: n,
+                                tree: ctx
+                                    .buildroot
+                                    .package_origin(n)
+                                    .last()
+                                    .map(|o| o.tree)
+                                    .unwrap_or_default(),
+                                path: p,
+                                version: None,
+                            })
+                            .collect();
+                        records.sort_by_key(|r| r.name);
                         if args.details {
-                            let items: BTreeMap<&String, String> = pkg_names
-                                .map(|n| {
-                                    let v = buildroot
-                                        .get_package_version(n)
-                                        .unwrap_or("unknown".to_string());
-                                    (n, v)
-                                })
+                            let infos: HashMap<&str, _> = ctx
+                                .buildroot
+                                .package_infos()
+                                .into_iter()
+                                .map(|(n, i)| (n.as_str(), i))
                                 .collect();
-                            for (n, v) in items {
-                                println!("{n:<32} {v}");
-                            }
-                        } else {
-                            let items: BTreeSet<&String> = pkg_names.collect();
-                            for item in items {
-                                println!("{item}");
+                            for record in records.iter_mut() {
+                                record.version = infos
+                                    .get(record.name)
+                                    .and_then(|i| i.as_ref().ok())
+                                    .map(|i| i.version().to_string())
+                                    .or(Some("unknown".to_string()));
                             }
                         }
-                        Ok(())
+                        output::print_records(ctx.format, "packages", &records, |r| {
+                            match r.version {
+                                Some(ref v) => format!("{:<32} {v}", r.name),
+                                None => r.name.to_string(),
+                            }
+                        })
                     }
+                    PackageCommand::Search(ref args) => self.search(ctx, args),
+                    PackageCommand::Show(ref args) => self.show(ctx, args),
                     PackageCommand::Bump(ref args) => {
-                        buildroot.set_package_version(&args.name, &args.version)
+                        ctx.buildroot
+                            .set_package_version(&args.name, &args.version)?;
+                        Ok(())
+                    }
+                }
+            }
+
+            fn show(&self, ctx: &Context, args: &ShowArgs) -> Result<()> {
+                let origins = ctx.buildroot.package_origin(&args.name);
+                let origin = origins
+                    .iter()
+                    .find(|o| !o.shadowed)
+                    .ok_or_else(|| Error::UnknownPackage(args.name.clone()))?;
+                let info = match ctx.buildroot.package_info(&args.name) {
+                    Ok(info) => info,
+                    // Virtual packages have no version.
+                    Err(Error::Package(package::Error::MissingVariable(_))) => {
+                        Arc::new(PackageInfo::from_path_unversioned(origin.path)?)
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let property = |n| info.property(n);
+                let record = DetailsRecord {
+                    name: info.name(),
+                    tree: origin.tree,
+                    path: origin.path,
+                    version: Some(info.version()).filter(|v| !v.is_empty()),
+                    site: property("site"),
+                    site_method: property("site_method"),
+                    source: property("source"),
+                    license: property("license"),
+                    license_files: property("license_files"),
+                    dependencies: info.dependencies(),
+                    infrastructures: info.infrastructures(),
+                    patches: ctx.buildroot.package_patches(&args.name)?,
+                    hashes: ctx.buildroot.package_hashes(&args.name)?,
+                };
+                output::print_record(ctx.format, &record, |r| {
+                    let mut lines = vec![];
+                    let mut add = |label: &str, values: Vec<String>| {
+                        let mut values = values.into_iter();
+                        let first = values.next().unwrap_or("-".to_string());
+                        lines.push(format!("{label:<16} {first}"));
+                        lines.extend(values.map(|v| format!("{:<16} {v}", "")));
+                    };
+                    let optional = |v: &Option<String>| v.iter().cloned().collect();
+                    add("Name:", vec![r.name.to_string()]);
+                    add("Tree:", vec![r.tree.to_string()]);
+                    add("Path:", vec![r.path.display().to_string()]);
+                    add(
+                        "Version:",
+                        r.version.iter().map(|v| v.to_string()).collect(),
+                    );
+                    add("Site:", optional(&r.site));
+                    add("Site method:", optional(&r.site_method));
+                    add("Source:", optional(&r.source));
+                    add("License:", optional(&r.license));
+                    add("License files:", optional(&r.license_files));
+                    add("Dependencies:", r.dependencies.clone());
+                    add("Infrastructure:", r.infrastructures.to_vec());
+                    add(
+                        "Patches:",
+                        r.patches
+                            .iter()
+                            .map(|p| {
+                                p.file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .to_string()
+                            })
+                            .collect(),
+                    );
+                    add(
+                        "Hashes:",
+                        r.hashes
+                            .iter()
+                            .map(|h| format!("{} {} {}", h.algorithm, h.hash, h.file))
+                            .collect(),
+                    );
+                    lines.join("\n")
+                })
+            }
+
+            fn search(&self, ctx: &Context, args: &SearchArgs) -> Result<()> {
+                let matcher = if args.fuzzy {
+                    Matcher::Fuzzy(args.pattern.to_lowercase())
+                } else {
+                    let regex = RegexBuilder::new(&args.pattern)
+                        .case_insensitive(true)
+                        .build()?;
+                    Matcher::Regex(regex)
+                };
+                let defconfig = match (&args.selected_by, &args.config) {
+                    (Some(name), _) => Some(ctx.buildroot.get_defconfig(name)?),
+                    (None, Some(path)) => Some(Defconfig::from_path(path)?),
+                    (None, None) => None,
+                };
+                let filter_info = args.infra.is_some() || args.license.is_some();
+                let license = args.license.as_ref().map(|l| l.to_lowercase());
+                let infos: HashMap<&String, Arc<PackageInfo>> = if filter_info {
+                    ctx.buildroot
+                        .package_infos()
+                        .into_iter()
+                        .filter_map(|(n, i)| Some((n, i.ok()?)))
+                        .collect()
+                } else {
+                    HashMap::new()
+                };
+                let mut records = vec![];
+                for (name, configs) in ctx.buildroot.all_package_configs() {
+                    if !ctx.has_package(name)
+                        || defconfig.as_ref().is_some_and(|d| !d.selects(name))
+                    {
+                        continue;
                     }
+                    // Packages which can not be parsed do not match.
+                    let Ok(configs) = configs else {
+                        continue;
+                    };
+                    if filter_info {
+                        let Some(info) = infos.get(name) else {
+                            continue;
+                        };
+                        if args
+                            .infra
+                            .as_ref()
+                            .is_some_and(|i| !info.uses_infrastructure(i))
+                        {
+                            continue;
+                        }
+                        let info_license = info.properties().get("license");
+                        if license.as_ref().is_some_and(|l| {
+                            info_license.is_none_or(|i| !i.to_lowercase().contains(l))
+                        }) {
+                            continue;
+                        }
+                    }
+                    let found = matcher.is_match(name)
+                        || configs.iter().any(|c| {
+                            c.prompt.as_ref().is_some_and(|p| matcher.is_match(p))
+                                || matcher.is_match(&c.help)
+                        });
+                    if found {
+                        records.push(MatchRecord {
+                            name,
+                            tree: ctx
+                                .buildroot
+                                .package_origin(name)
+                                .last()
+                                .map(|o| o.tree)
+                                .unwrap_or_default(),
+                            prompt: configs.into_iter().find_map(|c| c.prompt),
+                        });
+                    }
+                }
+                output::print_records(ctx.format, "packages", &records, |r| {
+                    format!("{:<32} {}", r.name, r.prompt.as_deref().unwrap_or(""))
+                })
+            }
+        }
+    }
+}
+
+mod utils {
+    use std::path::PathBuf;
+
+    pub fn user_cache_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|p| p.join("br2-utils"))
+    }
+}
+
+mod output {
+    use anyhow::{Context, Result};
+    use clap::ValueEnum;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    /// Format of the output of commands.
+    #[derive(Clone, Copy, Debug, ValueEnum)]
+    pub enum Format {
+        Text,
+        Json,
+        Toml,
+        Csv,
+    }
+
+    /// Print `records`, as a list named `key` for formats requiring a top-level table,
+    /// using `text` to format each record for humans.
+    pub fn print_records<T, F>(format: Format, key: &str, records: &[T], text: F) -> Result<()>
+    where
+        T: Serialize,
+        F: Fn(&T) -> String,
+    {
+        match serialize_records(format, key, records)? {
+            Some(s) => print!("{s}"),
+            None => {
+                for record in records {
+                    println!("{}", text(record));
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Print a single `record`, using `text` to format it for humans.
+    pub fn print_record<T, F>(format: Format, record: &T, text: F) -> Result<()>
+    where
+        T: Serialize,
+        F: Fn(&T) -> String,
+    {
+        match serialize_record(format, record)? {
+            Some(s) => print!("{s}"),
+            None => println!("{}", text(record)),
+        }
+        Ok(())
+    }
+
+    /// Serialize `records`, as a list named `key` for formats requiring a
+    /// top-level table.
+    ///
+    /// Return `None` for the text format, which is specific to each command.
+    pub fn serialize_records<T: Serialize>(
+        format: Format,
+        key: &str,
+        records: &[T],
+    ) -> Result<Option<String>> {
+        let s = match format {
+            Format::Text => return Ok(None),
+            Format::Json => format!("{}\n", serde_json::to_string_pretty(records)?),
+            Format::Toml => toml::to_string(&BTreeMap::from([(key, records)]))?,
+            Format::Csv => {
+                // Nested records can not be written as CSV: only return
+                // something once all the records have been serialized.
+                let mut writer = csv::Writer::from_writer(vec![]);
+                for record in records {
+                    writer
+                        .serialize(record)
+                        .with_context(|| "Format not supported by this command")?;
+                }
+                String::from_utf8(writer.into_inner()?)?
+            }
+        };
+        Ok(Some(s))
+    }
+
+    /// Serialize a single `record`, returning `None` for the text format.
+    pub fn serialize_record<T: Serialize>(format: Format, record: &T) -> Result<Option<String>> {
+        let s = match format {
+            Format::Text => return Ok(None),
+            Format::Json => format!("{}\n", serde_json::to_string_pretty(record)?),
+            Format::Toml => toml::to_string(record)?,
+            Format::Csv => return serialize_records(format, "", std::slice::from_ref(record)),
+        };
+        Ok(Some(s))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Record {
+            name: String,
+            version: Option<String>,
+            shadowed: bool,
+        }
+
+        fn records() -> Vec<Record> {
+            vec![
+                Record {
+                    name: "busybox".to_string(),
+                    version: Some("1.36.1".to_string()),
+                    shadowed: false,
+                },
+                Record {
+                    name: "foo, \"bar\"".to_string(),
+                    version: None,
+                    shadowed: true,
+                },
+            ]
+        }
+
+        #[test]
+        fn serialize_json() {
+            let s = serialize_records(Format::Json, "packages", &records())
+                .unwrap()
+                .unwrap();
+            let loaded: Vec<Record> = serde_json::from_str(&s).unwrap();
+            assert_eq!(loaded, records());
+        }
+
+        #[test]
+        fn serialize_toml() {
+            let s = serialize_records(Format::Toml, "packages", &records())
+                .unwrap()
+                .unwrap();
+            let mut loaded: BTreeMap<String, Vec<Record>> = toml::from_str(&s).unwrap();
+            assert_eq!(loaded.remove("packages"), Some(records()));
+            assert!(loaded.is_empty());
+
+            let s = serialize_record(Format::Toml, &records()[0])
+                .unwrap()
+                .unwrap();
+            assert_eq!(toml::from_str::<Record>(&s).unwrap(), records()[0]);
+        }
+
+        #[test]
+        fn serialize_csv() {
+            let s = serialize_records(Format::Csv, "packages", &records())
+                .unwrap()
+                .unwrap();
+            assert!(s.starts_with("name,version,shadowed\n"));
+            let loaded: Vec<Record> = csv::Reader::from_reader(s.as_bytes())
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(loaded, records());
+
+            #[derive(Serialize)]
+            struct Nested {
+                names: Vec<String>,
+            }
+            let nested = Nested {
+                names: vec!["foo".to_string()],
+            };
+            assert!(serialize_record(Format::Csv, &nested).is_err());
+        }
+
+        #[test]
+        fn serialize_text() {
+            assert!(serialize_records(Format::Text, "packages", &records())
+                .unwrap()
+                .is_none());
+        }
     }
 }
@@ -7,10 +7,13 @@
 //
 
 use anyhow::{anyhow, Context, Result};
-use br2_utils::mason::Mason;
+use br2_utils::{builder::BuildFailure, mason::Mason};
 use clap::{Parser, Subcommand};
-use commands::{add::Add, build::Build, delete::Delete, execute::Execute, list::List, show::Show};
-use std::path::PathBuf;
+use commands::{
+    add::Add, build::Build, delete::Delete, execute::Execute, list::List, log::Log,
+    overrides::Override, pkg::Pkg, sbom::Sbom, show::Show, stats::Stats,
+};
+use std::{path::PathBuf, process::ExitCode};
 
 #[derive(Debug, Subcommand)]
 enum Command {
@@ -24,8 +27,15 @@ enum Command {
     Execute(Execute),
     #[clap(visible_aliases = ["l", "ls"])]
     List(List),
+    Log(Log),
+    #[clap(visible_alias = "o")]
+    Override(Override),
+    #[clap(visible_alias = "p")]
+    Pkg(Pkg),
+    Sbom(Sbom),
     #[clap(visible_aliases = ["s", "sh"])]
     Show(Show),
+    Stats(Stats),
 }
 
 #[derive(Debug, Parser)]
@@ -33,17 +43,33 @@ enum Command {
 struct Cli {
     #[arg(short, long, help = " Path to build definitions")]
     storage: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Number of logs kept per build definition",
+        default_value_t = br2_utils::logs::DEFAULT_KEEP
+    )]
+    keep_logs: usize,
     #[command(subcommand, help = "Build command")]
     command: Command,
 }
 
-pub fn main() -> Result<()> {
+pub fn main() -> ExitCode {
     let args = Cli::parse();
+    // The output of make is hidden behind the status line, so show its end.
+    let excerpt = matches!(args.command, Command::Build(ref cmd) if cmd.status());
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => BuildFailure::report(e.as_ref(), excerpt),
+    }
+}
+
+fn run(args: Cli) -> Result<()> {
     let storage = args
         .storage
         .or_else(utils::user_local_storage)
         .ok_or(anyhow!("No storage found"))?;
-    let mason = Mason::new(storage);
+    let mut mason = Mason::new(storage);
+    mason.keep_logs(args.keep_logs);
     match args.command {
         Command::Add(ref cmd) => cmd
             .execute(&mason)
@@ -60,9 +86,24 @@ pub fn main() -> Result<()> {
         Command::List(ref cmd) => cmd
             .execute(&mason)
             .with_context(|| "Failed to list build definitions")?,
+        Command::Log(ref cmd) => cmd
+            .execute(&mason)
+            .with_context(|| "Failed to show build log")?,
+        Command::Override(ref cmd) => cmd
+            .execute(&mason)
+            .with_context(|| "Failed to manage source directory overrides")?,
+        Command::Pkg(ref cmd) => cmd
+            .execute(&mason)
+            .with_context(|| "Failed to perform package action")?,
+        Command::Sbom(ref cmd) => cmd
+            .execute(&mason)
+            .with_context(|| "Failed to generate SBOM")?,
         Command::Show(ref cmd) => cmd
             .execute(&mason)
             .with_context(|| "Failed to show build definition")?,
+        Command::Stats(ref cmd) => cmd
+            .execute(&mason)
+            .with_context(|| "Failed to analyze build times")?,
     }
     Ok(())
 }
@@ -75,8 +116,27 @@ mod commands {
         use br2_utils::{mason::Mason, BuildrootExplorer};
         use clap::Args;
 
+        use crate::utils::{parse_assignment, MakeArgs};
+
         #[derive(Debug, Args)]
         pub struct Add {
+            #[command(flatten)]
+            make: MakeArgs,
+            #[arg(
+                short = 'F',
+                long = "fragment",
+                help = "Path to config fragment merged into the defconfig",
+                value_name = "FRAGMENT"
+            )]
+            fragments: Vec<PathBuf>,
+            #[arg(
+                short = 'S',
+                long = "set",
+                help = "Symbol overriding the defconfig, like BR2_ENABLE_DEBUG=y",
+                value_name = "NAME=VALUE",
+                value_parser = parse_assignment
+            )]
+            symbols: Vec<(String, String)>,
             #[arg(short, long, help = "Path to main tree")]
             main: Option<PathBuf>,
             #[arg(
@@ -105,9 +165,19 @@ mod commands {
                 let buildroot = explorer
                     .explore()
                     .with_context(|| "Failed to explore Buildroot tree")?;
-                let builder = buildroot
+                let mut builder = buildroot
                     .create_builder(&self.defconfig, &self.output)
                     .with_context(|| "Failed to create Buildroot builder")?;
+                builder.options_mut().merge(&self.make.options());
+                for fragment in &self.fragments {
+                    let path = fragment.canonicalize().with_context(|| {
+                        format!("Failed to find fragment {}", fragment.display())
+                    })?;
+                    builder.add_fragment(path);
+                }
+                for (name, value) in &self.symbols {
+                    builder.set_symbol(name, value)?;
+                }
                 mason.add_from_builder(&self.name, &builder)?;
                 Ok(())
             }
@@ -115,22 +185,83 @@ mod commands {
     }
     pub mod build {
         use br2_utils::{
-            builder::BuildStep,
+            builder::{BuildStep, Builder},
             mason::{Error, Mason},
         };
         use clap::Args;
 
+        use crate::utils::{MakeArgs, StatusLine};
+
         #[derive(Debug, Args)]
         pub struct Build {
+            #[command(flatten)]
+            make: MakeArgs,
             #[arg(short, long, help = "Build step", default_value_t = BuildStep::All)]
             step: BuildStep,
+            #[arg(
+                long,
+                help = "Show the current package and step instead of the output",
+                conflicts_with = "no_log"
+            )]
+            status: bool,
+            #[arg(long, help = "Do not capture the output of make into a log file")]
+            no_log: bool,
+            #[arg(long, help = "Print the make commands instead of running them")]
+            dry_run: bool,
             #[arg(help = "Name of the build")]
             name: String,
         }
 
         impl Build {
+            pub fn status(&self) -> bool {
+                self.status
+            }
+
             pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
-                mason.build(&self.name, self.step)
+                let mut builder = mason.create_builder(&self.name)?;
+                if builder.is_outdated() {
+                    eprintln!(
+                        "Warning: build definition created for Buildroot {}, main tree is now {}",
+                        builder.recorded_version().unwrap_or_default(),
+                        builder
+                            .current_version()
+                            .map(|v| v.to_string())
+                            .unwrap_or_default()
+                    );
+                }
+                builder.options_mut().merge(&self.make.options());
+                builder.set_no_log(self.no_log);
+                if self.dry_run {
+                    for line in builder.step_dry_run_script(self.step)? {
+                        println!("{line}");
+                    }
+                    return Ok(());
+                }
+                if !self.status {
+                    builder.run_step(self.step)?;
+                    return Ok(());
+                }
+                let mut status = StatusLine::new();
+                let res = self.run_with_status(&builder, &mut status);
+                status.finish();
+                res
+            }
+
+            fn run_with_status(
+                &self,
+                builder: &Builder,
+                status: &mut StatusLine,
+            ) -> Result<(), Error> {
+                if !matches!(self.step, BuildStep::Main) {
+                    builder.run_step_with(BuildStep::Init, |e| status.update(e))?;
+                    if matches!(self.step, BuildStep::Init) {
+                        return Ok(());
+                    }
+                }
+                // The packages are only known once "defconfig" is built.
+                status.set_packages(builder.packages().ok());
+                builder.run_step_with(BuildStep::Main, |e| status.update(e))?;
+                Ok(())
             }
         }
     }
@@ -154,8 +285,16 @@ mod commands {
         use br2_utils::mason::{Error, Mason};
         use clap::Args;
 
+        use crate::utils::MakeArgs;
+
         #[derive(Debug, Args)]
         pub struct Execute {
+            #[command(flatten)]
+            make: MakeArgs,
+            #[arg(long, help = "Print the make command instead of running it")]
+            dry_run: bool,
+            #[arg(long, help = "Do not capture the output of make into a log file")]
+            no_log: bool,
             #[arg(help = "Name of the build")]
             name: String,
             #[arg(help = "Name of the target to build", value_name = "TARGET")]
@@ -164,7 +303,24 @@ mod commands {
 
         impl Execute {
             pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
-                mason.execute(&self.name, &self.targets)
+                let mut builder = mason.create_builder(&self.name)?;
+                builder.options_mut().merge(&self.make.options());
+                builder.set_no_log(self.no_log);
+                if self.dry_run {
+                    for line in builder.dry_run_script(&self.targets)? {
+                        println!("{line}");
+                    }
+                    return Ok(());
+                }
+                let generated = !builder.fragments().is_empty() || !builder.symbols().is_empty();
+                if generated && self.targets.iter().any(|t| t == "savedefconfig") {
+                    eprintln!(
+                        "Warning: saving to the generated defconfig {}, overwritten by the next \"defconfig\"",
+                        builder.defconfig_path().display()
+                    );
+                }
+                builder.build_targets(&self.targets)?;
+                Ok(())
             }
         }
     }
@@ -173,12 +329,189 @@ mod commands {
         use clap::Args;
 
         #[derive(Debug, Args)]
-        pub struct List;
+        pub struct List {
+            #[arg(
+                short,
+                long,
+                help = "Show recorded and current versions of the main tree"
+            )]
+            details: bool,
+        }
 
         impl List {
             pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
                 for entry in mason.list()? {
-                    println!("{entry}");
+                    if self.details {
+                        let builder = mason.create_builder(&entry)?;
+                        let recorded = builder.recorded_version().unwrap_or("unknown");
+                        let current = builder
+                            .current_version()
+                            .map(|v| v.to_string())
+                            .unwrap_or("unknown".to_string());
+                        let status = if builder.is_outdated() {
+                            " (outdated)"
+                        } else {
+                            ""
+                        };
+                        println!("{entry:<32} {recorded:<16} {current}{status}");
+                    } else {
+                        println!("{entry}");
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+    pub mod log {
+        use br2_utils::mason::{Error, Mason};
+        use clap::Args;
+
+        #[derive(Debug, Args)]
+        pub struct Log {
+            #[arg(long, help = "Show the log of the last failed build")]
+            failed: bool,
+            #[arg(short = 'n', long, help = "Show only the last lines", value_name = "N")]
+            tail: Option<usize>,
+            #[arg(help = "Name of the build")]
+            name: String,
+        }
+
+        impl Log {
+            pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
+                let entry = mason.logs(&self.name).latest(self.failed)?;
+                print!("{}", entry.read(self.tail)?);
+                Ok(())
+            }
+        }
+    }
+    pub mod overrides {
+        use br2_utils::{
+            builder::PackageAction,
+            mason::{Error, Mason},
+        };
+        use clap::Args;
+        use std::path::PathBuf;
+
+        use crate::utils::MakeArgs;
+
+        #[derive(Debug, Args)]
+        pub struct Override {
+            #[command(flatten)]
+            make: MakeArgs,
+            #[arg(short, long, help = "List the overridden packages", conflicts_with_all = ["package", "remove", "rebuild"])]
+            list: bool,
+            #[arg(
+                short,
+                long,
+                help = "Build the package from its regular sources again",
+                conflicts_with = "path"
+            )]
+            remove: bool,
+            #[arg(short = 'R', long, help = "Clean and rebuild the package")]
+            rebuild: bool,
+            #[arg(help = "Name of the build")]
+            name: String,
+            #[arg(help = "Name of the package", required_unless_present = "list")]
+            package: Option<String>,
+            #[arg(
+                help = "Path to the source directory of the package",
+                required_unless_present_any = ["list", "remove"]
+            )]
+            path: Option<PathBuf>,
+        }
+
+        impl Override {
+            pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
+                let mut builder = mason.create_builder(&self.name)?;
+                builder.options_mut().merge(&self.make.options());
+                let (Some(package), false) = (&self.package, self.list) else {
+                    for (package, path) in builder.overrides()? {
+                        println!("{package:<32} {}", path.display());
+                    }
+                    return Ok(());
+                };
+                match self.path {
+                    Some(ref path) => builder.set_override(package, path)?,
+                    None => {
+                        if !builder.remove_override(package)? {
+                            eprintln!("Warning: no source directory set for {package}");
+                            return Ok(());
+                        }
+                    }
+                }
+                if self.rebuild {
+                    // The build directory must not be reused when switching sources.
+                    builder.run_package_action(package, PackageAction::Dirclean)?;
+                    builder.run_package_action(package, PackageAction::Rebuild)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    pub mod pkg {
+        use br2_utils::{
+            builder::PackageAction,
+            mason::{Error, Mason},
+        };
+        use clap::Args;
+
+        use crate::utils::MakeArgs;
+
+        #[derive(Debug, Args)]
+        pub struct Pkg {
+            #[command(flatten)]
+            make: MakeArgs,
+            #[arg(long, help = "Do not capture the output of make into a log file")]
+            no_log: bool,
+            #[arg(help = "Name of the build")]
+            name: String,
+            #[arg(help = "Name of the package")]
+            package: String,
+            #[arg(
+                help = "Action (rebuild, reconfigure, reinstall, dirclean, source, extract or legal-info)"
+            )]
+            action: PackageAction,
+        }
+
+        impl Pkg {
+            pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
+                let mut builder = mason.create_builder(&self.name)?;
+                builder.options_mut().merge(&self.make.options());
+                builder.set_no_log(self.no_log);
+                builder.run_package_action(&self.package, self.action)?;
+                Ok(())
+            }
+        }
+    }
+    pub mod sbom {
+        use br2_utils::{
+            mason::{Error, Mason},
+            sbom::SbomFormat,
+        };
+        use clap::Args;
+        use std::path::PathBuf;
+
+        #[derive(Debug, Args)]
+        pub struct Sbom {
+            #[arg(
+                short,
+                long,
+                help = "Format of the SBOM (spdx or cyclonedx)",
+                default_value_t = SbomFormat::Spdx
+            )]
+            format: SbomFormat,
+            #[arg(short, long, help = "Path to output file [default: standard output]")]
+            output: Option<PathBuf>,
+            #[arg(help = "Name of the build")]
+            name: String,
+        }
+
+        impl Sbom {
+            pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
+                let text = mason.sbom(&self.name, self.format)?;
+                match self.output {
+                    Some(ref path) => std::fs::write(path, text)?,
+                    None => println!("{text}"),
                 }
                 Ok(())
             }
@@ -200,12 +533,268 @@ mod commands {
             }
         }
     }
+    pub mod stats {
+        use br2_utils::{
+            mason::{Error, Mason},
+            stats::{self, BuildTimes},
+        };
+        use clap::{Args, ValueEnum};
+        use std::{fs::File, io::Write, path::PathBuf};
+
+        #[derive(Clone, Copy, Debug, ValueEnum)]
+        enum StatsFormat {
+            Text,
+            Csv,
+        }
+
+        #[derive(Debug, Args)]
+        pub struct Stats {
+            #[arg(
+                short,
+                long,
+                help = "Output format",
+                value_enum,
+                default_value_t = StatsFormat::Text
+            )]
+            format: StatsFormat,
+            #[arg(
+                long,
+                help = "Export the times as a Chrome trace (JSON)",
+                conflicts_with_all = ["format", "compare"]
+            )]
+            trace: bool,
+            #[arg(short, long, help = "Path to output file [default: standard output]")]
+            output: Option<PathBuf>,
+            #[arg(
+                short = 'n',
+                long,
+                help = "Number of entries shown",
+                default_value_t = 10
+            )]
+            top: usize,
+            #[arg(
+                short,
+                long,
+                help = "Name of a build to compare with",
+                value_name = "BUILD"
+            )]
+            compare: Option<String>,
+            #[arg(help = "Name of the build")]
+            name: String,
+        }
+
+        impl Stats {
+            pub fn execute(&self, mason: &Mason) -> Result<(), Error> {
+                let times = mason.build_times(&self.name)?;
+                let mut writer: Box<dyn Write> = match self.output {
+                    Some(ref path) => Box::new(File::create(path)?),
+                    None => Box::new(std::io::stdout()),
+                };
+                match self.compare {
+                    Some(ref other) => {
+                        let other = mason.build_times(other)?;
+                        self.write_comparison(&times, &other, &mut writer)
+                    }
+                    None => self.write_times(&times, &mut writer),
+                }
+            }
+
+            fn write_times(&self, times: &BuildTimes, writer: &mut dyn Write) -> Result<(), Error> {
+                if self.trace {
+                    serde_json::to_writer_pretty(&mut *writer, &times.to_chrome_trace())?;
+                    writeln!(writer)?;
+                    return Ok(());
+                }
+                match self.format {
+                    StatsFormat::Csv => times.write_csv(writer)?,
+                    StatsFormat::Text => {
+                        writeln!(writer, "Total: {}", duration(times.total()))?;
+                        writeln!(writer, "\nSlowest packages:")?;
+                        for package in times.packages().iter().take(self.top) {
+                            let name = &package.package;
+                            writeln!(writer, "  {name:<32} {:>10}", duration(package.duration))?;
+                        }
+                        writeln!(writer, "\nSlowest steps:")?;
+                        for step in times.slowest_steps().iter().take(self.top) {
+                            let name = format!("{} {}", step.package, step.step);
+                            writeln!(writer, "  {name:<32} {:>10}", duration(step.duration()))?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+
+            fn write_comparison(
+                &self,
+                before: &BuildTimes,
+                after: &BuildTimes,
+                writer: &mut dyn Write,
+            ) -> Result<(), Error> {
+                let diffs = before.compare(after);
+                match self.format {
+                    StatsFormat::Csv => stats::write_comparison_csv(&diffs, writer)?,
+                    StatsFormat::Text => {
+                        let (total_before, total_after) = (before.total(), after.total());
+                        writeln!(
+                            writer,
+                            "Total: {} -> {} ({:+.1} s)",
+                            duration(total_before),
+                            duration(total_after),
+                            total_after - total_before
+                        )?;
+                        writeln!(writer, "\nLargest changes:")?;
+                        for diff in diffs.iter().take(self.top) {
+                            let secs = |d: Option<f64>| d.map(duration).unwrap_or("-".to_string());
+                            writeln!(
+                                writer,
+                                "  {:<32} {:>10} -> {:>10} ({:+.1} s)",
+                                diff.package,
+                                secs(diff.before),
+                                secs(diff.after),
+                                diff.delta()
+                            )?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        fn duration(secs: f64) -> String {
+            format!("{secs:.1} s")
+        }
+    }
 }
 
 mod utils {
-    use std::path::PathBuf;
+    use br2_utils::builder::{BuildEvent, MakeOptions};
+    use clap::Args;
+    use std::{
+        collections::BTreeSet,
+        io::{IsTerminal, Write},
+        path::PathBuf,
+    };
 
     pub fn user_local_storage() -> Option<PathBuf> {
         dirs::config_local_dir().map(|p| p.join("br2-utils"))
     }
+
+    // Options of the make invocation, overriding the build definition.
+    #[derive(Debug, Args)]
+    pub struct MakeArgs {
+        #[arg(short, long, help = "Number of jobs used to build each package")]
+        jobs: Option<u32>,
+        #[arg(long, help = "Path to the download directory")]
+        dl_dir: Option<PathBuf>,
+        #[arg(long, help = "Path to the compiler cache directory")]
+        ccache_dir: Option<PathBuf>,
+        #[arg(long, help = "Path to the make program")]
+        make: Option<PathBuf>,
+        #[arg(
+            short = 'V',
+            long = "var",
+            help = "Extra make variable",
+            value_name = "NAME=VALUE",
+            value_parser = parse_assignment
+        )]
+        variables: Vec<(String, String)>,
+        #[arg(
+            short = 'E',
+            long = "env",
+            help = "Extra environment variable",
+            value_name = "NAME=VALUE",
+            value_parser = parse_assignment
+        )]
+        env: Vec<(String, String)>,
+    }
+
+    impl MakeArgs {
+        pub fn options(&self) -> MakeOptions {
+            MakeOptions {
+                make: self.make.clone(),
+                jobs: self.jobs,
+                dl_dir: self.dl_dir.clone(),
+                ccache_dir: self.ccache_dir.clone(),
+                variables: self.variables.iter().cloned().collect(),
+                env: self.env.iter().cloned().collect(),
+            }
+        }
+    }
+
+    pub fn parse_assignment(s: &str) -> Result<(String, String), String> {
+        match s.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+            _ => Err(format!("invalid assignment: {s}")),
+        }
+    }
+
+    // Single line showing the progress of a build.
+    //
+    // When standard error is not a terminal, one line is printed per update.
+    pub struct StatusLine {
+        packages: Option<BTreeSet<String>>,
+        done: BTreeSet<String>,
+        tty: bool,
+    }
+
+    impl StatusLine {
+        const WIDTH: usize = 79;
+
+        pub fn new() -> Self {
+            Self {
+                packages: None,
+                done: BTreeSet::new(),
+                tty: std::io::stderr().is_terminal(),
+            }
+        }
+
+        // Set the packages to build, as given by `Builder::packages()`.
+        //
+        // Only these packages are counted, so that the host packages pulled
+        // in as dependencies do not exceed the total.
+        pub fn set_packages(&mut self, packages: Option<Vec<String>>) {
+            self.packages = packages.map(|p| p.into_iter().collect());
+        }
+
+        pub fn update(&mut self, event: BuildEvent) {
+            let BuildEvent::Progress(progress) = event else {
+                return;
+            };
+            let text = match progress.package {
+                Some(ref package) => {
+                    let counted = self.packages.as_ref().is_none_or(|p| p.contains(package));
+                    if progress.step.is_install() && counted {
+                        self.done.insert(package.clone());
+                    }
+                    let total = self
+                        .packages
+                        .as_ref()
+                        .map(|p| p.len().to_string())
+                        .unwrap_or("?".to_string());
+                    let version = progress.version.map(|v| format!(" {v}"));
+                    format!(
+                        "[{}/{total}] {package}{}: {}",
+                        self.done.len(),
+                        version.unwrap_or_default(),
+                        progress.step
+                    )
+                }
+                None => progress.step.to_string(),
+            };
+            let text: String = text.chars().take(Self::WIDTH).collect();
+            let mut stderr = std::io::stderr();
+            if self.tty {
+                let _ = write!(stderr, "\r\x1b[K{text}");
+            } else {
+                let _ = writeln!(stderr, "{text}");
+            }
+            let _ = stderr.flush();
+        }
+
+        pub fn finish(&self) {
+            if self.tty {
+                eprintln!();
+            }
+        }
+    }
 }
@@ -0,0 +1,381 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for handling the board files referenced by defconfigs.
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use super::defconfig::{Defconfig, SymbolValue};
+
+/// Kind of value of a symbol referencing files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReferenceKind {
+    /// A single path
+    Single,
+    /// A list of paths, separated by spaces
+    List,
+    /// Arguments of post-build and post-image scripts, where `-c <path>`
+    /// gives the configuration of `genimage`
+    ScriptArgs,
+}
+
+/// Directories of a tree holding files referenced by defconfigs.
+const BOARD_DIRS: [&str; 3] = ["board", "configs", "patches"];
+
+/// Symbols of a defconfig referencing files, in board directories or elsewhere.
+const BOARD_SYMBOLS: [(&str, ReferenceKind); 19] = [
+    ("BR2_GLOBAL_PATCH_DIR", ReferenceKind::List),
+    (
+        "BR2_LINUX_KERNEL_CONFIG_FRAGMENT_FILES",
+        ReferenceKind::List,
+    ),
+    ("BR2_LINUX_KERNEL_CUSTOM_CONFIG_FILE", ReferenceKind::Single),
+    ("BR2_LINUX_KERNEL_CUSTOM_DTS_PATH", ReferenceKind::List),
+    ("BR2_LINUX_KERNEL_PATCH", ReferenceKind::List),
+    ("BR2_PACKAGE_BUSYBOX_CONFIG", ReferenceKind::Single),
+    (
+        "BR2_PACKAGE_BUSYBOX_CONFIG_FRAGMENT_FILES",
+        ReferenceKind::List,
+    ),
+    ("BR2_ROOTFS_DEVICE_TABLE", ReferenceKind::List),
+    ("BR2_ROOTFS_OVERLAY", ReferenceKind::List),
+    ("BR2_ROOTFS_POST_BUILD_SCRIPT", ReferenceKind::List),
+    ("BR2_ROOTFS_POST_FAKEROOT_SCRIPT", ReferenceKind::List),
+    ("BR2_ROOTFS_POST_IMAGE_SCRIPT", ReferenceKind::List),
+    ("BR2_ROOTFS_POST_SCRIPT_ARGS", ReferenceKind::ScriptArgs),
+    ("BR2_ROOTFS_STATIC_DEVICE_TABLE", ReferenceKind::List),
+    ("BR2_ROOTFS_USERS_TABLES", ReferenceKind::List),
+    (
+        "BR2_TARGET_BAREBOX_CUSTOM_CONFIG_FILE",
+        ReferenceKind::Single,
+    ),
+    (
+        "BR2_TARGET_UBOOT_CONFIG_FRAGMENT_FILES",
+        ReferenceKind::List,
+    ),
+    ("BR2_TARGET_UBOOT_CUSTOM_CONFIG_FILE", ReferenceKind::Single),
+    ("BR2_TARGET_UBOOT_PATCH", ReferenceKind::List),
+];
+
+/// A file referenced by a symbol of a defconfig.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BoardFile {
+    /// Name of the symbol referencing the file.
+    pub symbol: String,
+    /// Path to the file, with variables expanded.
+    pub path: PathBuf,
+    /// Whether the file exists.
+    pub exists: bool,
+}
+
+/// Files referenced by a defconfig, and the board directory holding them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Board {
+    /// Name of the defconfig.
+    pub defconfig: String,
+    /// Board directory, i.e. the deepest directory below a `board` directory
+    /// holding all the referenced files located in a `board` directory.
+    pub directory: Option<PathBuf>,
+    /// Files referenced by the defconfig.
+    pub files: Vec<BoardFile>,
+}
+
+impl Board {
+    /// Collect the files referenced by `defconfig`, named `name`.
+    ///
+    /// Variables like `$(TOPDIR)` or `$(BR2_EXTERNAL_FOO_PATH)` are expanded
+    /// using `variables`, and relative paths are resolved from `topdir`, the
+    /// path to the main tree, where Buildroot runs.
+    pub fn from_defconfig(
+        name: &str,
+        defconfig: &Defconfig,
+        topdir: &Path,
+        variables: &HashMap<String, PathBuf>,
+    ) -> Self {
+        let mut files = vec![];
+        for symbol in defconfig.symbols() {
+            let kind = match BOARD_SYMBOLS.iter().find(|(n, _)| *n == symbol.name) {
+                Some((_, kind)) => *kind,
+                None => continue,
+            };
+            let value = match symbol.value {
+                SymbolValue::String(ref v) => v,
+                _ => continue,
+            };
+            for reference in split_references(value, kind) {
+                if reference.contains("://") {
+                    // Patches may also be downloaded.
+                    continue;
+                }
+                let path = topdir.join(expand_variables(reference, topdir, variables));
+                let path = normalize(&path);
+                files.push(BoardFile {
+                    symbol: symbol.name.clone(),
+                    exists: path.exists(),
+                    path,
+                });
+            }
+        }
+        let directory = board_directory(files.iter().map(|f| f.path.as_path()));
+        Self {
+            defconfig: name.to_string(),
+            directory,
+            files,
+        }
+    }
+
+    /// Return an iterator over the referenced files which do not exist.
+    pub fn missing(&self) -> impl Iterator<Item = &BoardFile> {
+        self.files.iter().filter(|f| !f.exists)
+    }
+}
+
+/// Find the files under the `board`, `configs` and `patches` directories of
+/// the trees at `roots` which are not referenced by any of `boards`.
+///
+/// A file is considered as referenced if it is a defconfig, if it is or lies
+/// in a referenced path, or if its path relative to a referenced script
+/// located in the same directory or above appears as a whole word in this
+/// script, like `genimage.cfg` used by `post-image.sh` via `${BOARD_DIR}`.
+pub fn find_orphans<P: AsRef<Path>>(
+    roots: &[P],
+    boards: &[Board],
+) -> Result<Vec<PathBuf>, walkdir::Error> {
+    let referenced: Vec<&Path> = boards
+        .iter()
+        .flat_map(|b| b.files.iter())
+        .filter(|f| f.exists)
+        .map(|f| f.path.as_path())
+        .collect();
+    let scripts: Vec<(&Path, String)> = boards
+        .iter()
+        .flat_map(|b| b.files.iter())
+        .filter(|f| f.symbol.ends_with("_SCRIPT") && f.path.is_file())
+        .filter_map(|f| {
+            let dir = f.path.parent()?;
+            fs::read_to_string(&f.path).ok().map(|c| (dir, c))
+        })
+        .collect();
+
+    let mut orphans = vec![];
+    for root in roots {
+        for dir in BOARD_DIRS.iter().map(|d| root.as_ref().join(d)) {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in WalkDir::new(&dir).sort_by_file_name() {
+                let entry = entry?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy();
+                if name.ends_with("_defconfig")
+                    || referenced.iter().any(|r| path.starts_with(r))
+                    || scripts.iter().any(|(d, c)| {
+                        path.strip_prefix(d)
+                            .is_ok_and(|p| mentions(c, &p.to_string_lossy()))
+                    })
+                {
+                    continue;
+                }
+                orphans.push(entry.into_path());
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+/// Return whether `script` mentions `path` as a whole word, or right after
+/// a variable like `${BOARD_DIR}/`.
+fn mentions(script: &str, path: &str) -> bool {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || "_.-".contains(c);
+    script.match_indices(path).any(|(i, _)| {
+        let before = &script[..i];
+        let after = &script[i + path.len()..];
+        let starts = match before.strip_suffix('/') {
+            Some(b) => {
+                b.ends_with(['}', ')'])
+                    || b.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                        .ends_with('$')
+            }
+            None => !before.ends_with(is_name),
+        };
+        starts && !after.starts_with(is_name)
+    })
+}
+
+fn split_references(value: &str, kind: ReferenceKind) -> Vec<&str> {
+    match kind {
+        ReferenceKind::Single => Some(value.trim())
+            .filter(|v| !v.is_empty())
+            .into_iter()
+            .collect(),
+        ReferenceKind::List => value.split_whitespace().collect(),
+        ReferenceKind::ScriptArgs => value
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .windows(2)
+            .filter(|w| w[0] == "-c" || w[0] == "--config")
+            .map(|w| w[1])
+            .collect(),
+    }
+}
+
+/// Expand `$(VAR)` and `${VAR}` in `value`, leaving unknown variables as is.
+fn expand_variables(value: &str, topdir: &Path, variables: &HashMap<String, PathBuf>) -> String {
+    lazy_static! {
+        static ref VARIABLE: Regex = Regex::new(r"\$[({]([A-Za-z0-9_]+)[)}]").unwrap();
+    }
+
+    VARIABLE
+        .replace_all(value, |caps: &Captures| match &caps[1] {
+            "TOPDIR" | "BR2_TOPDIR" => topdir.to_string_lossy().to_string(),
+            v => variables
+                .get(v)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|| caps[0].to_string()),
+        })
+        .to_string()
+}
+
+/// Remove `.` and `..` components from `path`, without accessing the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn board_directory<'a, I: Iterator<Item = &'a Path>>(paths: I) -> Option<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for path in paths.filter(|p| p.components().any(|c| c.as_os_str() == "board")) {
+        common = match common {
+            None => path.parent().map(Path::to_path_buf),
+            Some(c) => Some(
+                c.components()
+                    .zip(path.components())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect(),
+            ),
+        };
+    }
+    // The board directory is below a "board" directory, not the latter itself.
+    common.filter(|c| {
+        c.file_name().is_some_and(|n| n != "board")
+            && c.components().any(|c| c.as_os_str() == "board")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFCONFIG_BOARD: &str = r#"
+BR2_ROOTFS_OVERLAY="board/acme/foo/rootfs_overlay $(BR2_EXTERNAL_ACME_PATH)/board/acme/common/overlay"
+BR2_ROOTFS_POST_IMAGE_SCRIPT="board/acme/foo/post-image.sh"
+BR2_ROOTFS_POST_SCRIPT_ARGS="-c board/acme/foo/genimage.cfg"
+BR2_LINUX_KERNEL_CUSTOM_CONFIG_FILE="$(TOPDIR)/board/acme/foo/../foo/linux.config"
+BR2_LINUX_KERNEL_PATCH="https://some.where/fix.patch"
+"#;
+
+    #[test]
+    fn collect_board_files() {
+        let defconfig = Defconfig::from_reader(DEFCONFIG_BOARD.as_bytes()).unwrap();
+        let variables =
+            HashMap::from([("BR2_EXTERNAL_ACME_PATH".to_string(), PathBuf::from("/ext"))]);
+        let board =
+            Board::from_defconfig("foo_defconfig", &defconfig, Path::new("/br"), &variables);
+        let paths: Vec<&Path> = board.files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/br/board/acme/foo/rootfs_overlay"),
+                Path::new("/ext/board/acme/common/overlay"),
+                Path::new("/br/board/acme/foo/post-image.sh"),
+                Path::new("/br/board/acme/foo/genimage.cfg"),
+                Path::new("/br/board/acme/foo/linux.config"),
+            ]
+        );
+        assert_eq!(board.missing().count(), 5);
+        assert_eq!(board.directory, None);
+    }
+
+    #[test]
+    fn find_orphan_files() {
+        let path = tempfile::Builder::new()
+            .prefix("br2-utils-test")
+            .tempdir()
+            .unwrap();
+        let board_dir = path.path().join("board").join("acme");
+        let overlay_dir = board_dir.join("rootfs_overlay").join("etc");
+        fs::create_dir_all(&overlay_dir).unwrap();
+        fs::create_dir_all(path.path().join("configs")).unwrap();
+        fs::write(overlay_dir.join("issue"), "Welcome\n").unwrap();
+        fs::write(
+            board_dir.join("post-image.sh"),
+            "genimage -c genimage.cfg\ncp $BOARD_DIR/users.txt /etc/fstab \"${BOARD_DIR}/etc/issue\"\n",
+        )
+        .unwrap();
+        fs::write(board_dir.join("genimage.cfg"), "").unwrap();
+        fs::write(board_dir.join("users.txt"), "").unwrap();
+        fs::write(board_dir.join("fstab"), "").unwrap();
+        fs::write(board_dir.join("issue"), "").unwrap();
+        fs::write(board_dir.join("linux-old.config"), "").unwrap();
+        fs::write(path.path().join("configs").join("acme_defconfig"), "").unwrap();
+        let defconfig = r#"
+BR2_ROOTFS_OVERLAY="$(BR2_EXTERNAL_ACME_PATH)/board/acme/rootfs_overlay"
+BR2_ROOTFS_POST_IMAGE_SCRIPT="$(BR2_EXTERNAL_ACME_PATH)/board/acme/post-image.sh"
+"#;
+        let defconfig = Defconfig::from_reader(defconfig.as_bytes()).unwrap();
+        let variables = HashMap::from([(
+            "BR2_EXTERNAL_ACME_PATH".to_string(),
+            path.path().to_path_buf(),
+        )]);
+        let board =
+            Board::from_defconfig("acme_defconfig", &defconfig, Path::new("/br"), &variables);
+        let orphans = find_orphans(&[path.path()], &[board]).unwrap();
+        assert_eq!(
+            orphans,
+            [
+                board_dir.join("fstab"),
+                board_dir.join("issue"),
+                board_dir.join("linux-old.config"),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_board_directory() {
+        let paths = [
+            Path::new("/br/board/acme/foo/rootfs_overlay"),
+            Path::new("/br/board/acme/foo/post-build.sh"),
+            Path::new("/br/package/foo/foo.mk"),
+        ];
+        assert_eq!(
+            board_directory(paths.into_iter()),
+            Some(PathBuf::from("/br/board/acme/foo"))
+        );
+    }
+}
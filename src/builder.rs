@@ -8,24 +8,147 @@
 
 //! Provide helpers for building using a defconfig.
 
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Stdio},
+    str::FromStr,
+    sync::Mutex,
+};
 use thiserror::Error;
 use toml;
 
+use super::{
+    defconfig::{self, Defconfig, Symbol, SymbolValue},
+    logs::{self, BuildLog, BuildLogs, Stream},
+    overrides::{self, OverrideFile},
+    shell::{shell_command, write_file_command},
+    version::BuildrootVersion,
+    Buildroot, BuildrootExplorer,
+};
+
 /// Errors reported when performing a build
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Build failed")]
-    BuildFailed,
+    #[error("Build failed: {0}")]
+    BuildFailed(Box<BuildFailure>),
+    #[error("Defconfig error: {0}")]
+    Defconfig(#[from] defconfig::Error),
     #[error("Invalid step")]
     InvalidStep,
+    #[error("Invalid package action: {0}")]
+    InvalidPackageAction(String),
+    #[error("Invalid external tree path (spaces and colons are not supported): {0:?}")]
+    InvalidExternalPath(PathBuf),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Log error: {0}")]
+    Logs(#[from] logs::Error),
+    #[error("Override error: {0}")]
+    Override(#[from] overrides::Error),
+    #[error("Tree exploration error: {0}")]
+    Explore(#[from] Box<super::Error>),
     #[error("TOML deserialization error: {0}")]
     TomlDeserialize(#[from] toml::de::Error),
     #[error("TOML serialization error: {0}")]
     TomlSerialize(#[from] toml::ser::Error),
+    #[error("Unsupported variable in BR2_PACKAGE_OVERRIDE_FILE: {0}")]
+    UnsupportedOverrideFile(String),
+    #[error("Unknown package: {0}")]
+    UnknownPackage(String),
+}
+
+/// Name of the defconfig generated in the output directory
+const GENERATED_DEFCONFIG: &str = "br2-utils_defconfig";
+
+/// Number of lines of the output kept when a build fails
+const EXCERPT_LINES: usize = 20;
+
+/// Details about a failed build
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildFailure {
+    /// Targets being built
+    pub target: String,
+    /// Package being built when the build failed, if any
+    pub package: Option<String>,
+    /// Step being performed when the build failed, if any
+    pub step: Option<PackageStep>,
+    /// Exit code of make, if it was not killed by a signal
+    pub exit_code: Option<i32>,
+    /// Last lines of the output of make
+    pub log_excerpt: Vec<String>,
+    /// Path to the log file of the build
+    pub log: Option<PathBuf>,
+}
+
+impl std::fmt::Display for BuildFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "make {}", self.target)?;
+        if let Some(ref package) = self.package {
+            write!(f, ", package {package}")?;
+        }
+        if let Some(ref step) = self.step {
+            write!(f, ", step \"{step}\"")?;
+        }
+        match self.exit_code {
+            Some(code) => write!(f, ", exit code {code}"),
+            None => write!(f, ", killed by a signal"),
+        }
+    }
+}
+
+impl BuildFailure {
+    /// Find the failed build among `err` and its sources
+    pub fn find<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a BuildFailure> {
+        std::iter::successors(Some(err), |e| e.source()).find_map(|e| match e.downcast_ref() {
+            Some(Error::BuildFailed(f)) => Some(f.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Print `err` and its sources to the standard error, after the log of
+    /// the failed build it holds, if any
+    ///
+    /// The last lines of the output of make are printed only if `excerpt` is
+    /// set, as they are already on the terminal otherwise.
+    ///
+    /// Return the exit code of make, or a generic failure.
+    pub fn report(err: &(dyn std::error::Error + 'static), excerpt: bool) -> ExitCode {
+        let failure = Self::find(err);
+        if let Some(failure) = failure {
+            if excerpt {
+                for line in &failure.log_excerpt {
+                    eprintln!("{line}");
+                }
+            }
+            if let Some(ref log) = failure.log {
+                eprintln!("Build log: {}", log.display());
+            }
+        }
+        eprintln!("Error: {err}");
+        let mut source = err.source();
+        if source.is_some() {
+            eprintln!("\nCaused by:");
+        }
+        while let Some(e) = source {
+            eprintln!("    {e}");
+            source = e.source();
+        }
+        failure
+            .and_then(BuildFailure::status_code)
+            .map_or(ExitCode::FAILURE, ExitCode::from)
+    }
+
+    // Exit code of make, if it is a valid and non-zero exit code of a program.
+    fn status_code(&self) -> Option<u8> {
+        self.exit_code
+            .and_then(|c| u8::try_from(c).ok())
+            .filter(|&c| c != 0)
+    }
 }
 
 /// Represent a Buildroot builder
@@ -35,6 +158,75 @@ pub struct Builder {
     pub(crate) output: PathBuf,
     pub(crate) main: PathBuf,
     pub(crate) externals: Vec<PathBuf>,
+    /// Version of the main tree when the builder was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
+    /// Config fragments merged into the defconfig, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) fragments: Vec<PathBuf>,
+    /// Options of the make invocation
+    #[serde(default, skip_serializing_if = "MakeOptions::is_empty")]
+    pub(crate) options: MakeOptions,
+    /// Symbols overriding the ones of the defconfig and fragments, with
+    /// values as written in a defconfig, like `"y"` or `"\"text\""`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) symbols: BTreeMap<String, String>,
+    /// Location of the build logs, `<output>/logs` if not set
+    #[serde(skip)]
+    pub(crate) logs: Option<BuildLogs>,
+    /// Whether the output of the builds is left on the terminal instead of
+    /// being captured into logs
+    #[serde(skip)]
+    pub(crate) no_log: bool,
+}
+
+/// Options of the make invocation of a builder
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct MakeOptions {
+    /// Path to the make program
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub make: Option<PathBuf>,
+    /// Number of jobs used to build each package, passed as `BR2_JLEVEL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<u32>,
+    /// Location of the downloads, overriding `BR2_DL_DIR`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dl_dir: Option<PathBuf>,
+    /// Location of the compiler cache, overriding `BR2_CCACHE_DIR`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ccache_dir: Option<PathBuf>,
+    /// Extra make variables, like `V=1`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// Extra environment variables
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl MakeOptions {
+    /// Check if no option is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Override the options with the ones set in `other`
+    pub fn merge(&mut self, other: &MakeOptions) {
+        if other.make.is_some() {
+            self.make = other.make.clone();
+        }
+        if other.jobs.is_some() {
+            self.jobs = other.jobs;
+        }
+        if other.dl_dir.is_some() {
+            self.dl_dir = other.dl_dir.clone();
+        }
+        if other.ccache_dir.is_some() {
+            self.ccache_dir = other.ccache_dir.clone();
+        }
+        self.variables.extend(other.variables.clone());
+        self.env.extend(other.env.clone());
+    }
 }
 
 /// Represent a build step
@@ -71,42 +263,645 @@ impl std::fmt::Display for BuildStep {
     }
 }
 
+/// Represent an operation on a single package
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageAction {
+    /// Restart the build from the build step
+    Rebuild,
+    /// Restart the build from the configure step
+    Reconfigure,
+    /// Restart the build from the install step
+    Reinstall,
+    /// Remove the build directory
+    Dirclean,
+    /// Download the sources
+    Source,
+    /// Download and extract the sources
+    Extract,
+    /// Save the sources and license files
+    LegalInfo,
+}
+
+impl PackageAction {
+    /// Return the make target performing the action on `package`
+    pub fn target(&self, package: &str) -> String {
+        format!("{package}-{self}")
+    }
+}
+
+impl FromStr for PackageAction {
+    type Err = self::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "rebuild" => Ok(PackageAction::Rebuild),
+            "reconfigure" => Ok(PackageAction::Reconfigure),
+            "reinstall" => Ok(PackageAction::Reinstall),
+            "dirclean" => Ok(PackageAction::Dirclean),
+            "source" => Ok(PackageAction::Source),
+            "extract" => Ok(PackageAction::Extract),
+            "legal-info" => Ok(PackageAction::LegalInfo),
+            _ => Err(Error::InvalidPackageAction(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageAction::Rebuild => write!(f, "rebuild"),
+            PackageAction::Reconfigure => write!(f, "reconfigure"),
+            PackageAction::Reinstall => write!(f, "reinstall"),
+            PackageAction::Dirclean => write!(f, "dirclean"),
+            PackageAction::Source => write!(f, "source"),
+            PackageAction::Extract => write!(f, "extract"),
+            PackageAction::LegalInfo => write!(f, "legal-info"),
+        }
+    }
+}
+
+/// Step of the build of a package, as reported by Buildroot
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackageStep {
+    Downloading,
+    Extracting,
+    Patching,
+    Configuring,
+    Building,
+    InstallingHost,
+    InstallingStaging,
+    InstallingTarget,
+    InstallingImages,
+    /// Any other step, like "Finalizing target directory"
+    Other(String),
+}
+
+impl PackageStep {
+    /// Check if the step installs the package
+    pub fn is_install(&self) -> bool {
+        matches!(
+            self,
+            PackageStep::InstallingHost
+                | PackageStep::InstallingStaging
+                | PackageStep::InstallingTarget
+                | PackageStep::InstallingImages
+        )
+    }
+}
+
+impl From<&str> for PackageStep {
+    fn from(s: &str) -> Self {
+        match s {
+            "Downloading" => PackageStep::Downloading,
+            "Extracting" => PackageStep::Extracting,
+            "Patching" => PackageStep::Patching,
+            "Configuring" => PackageStep::Configuring,
+            "Building" => PackageStep::Building,
+            "Installing to host directory" => PackageStep::InstallingHost,
+            "Installing to staging directory" => PackageStep::InstallingStaging,
+            "Installing to target" => PackageStep::InstallingTarget,
+            "Installing to images directory" => PackageStep::InstallingImages,
+            _ => PackageStep::Other(s.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageStep::Downloading => write!(f, "Downloading"),
+            PackageStep::Extracting => write!(f, "Extracting"),
+            PackageStep::Patching => write!(f, "Patching"),
+            PackageStep::Configuring => write!(f, "Configuring"),
+            PackageStep::Building => write!(f, "Building"),
+            PackageStep::InstallingHost => write!(f, "Installing to host directory"),
+            PackageStep::InstallingStaging => write!(f, "Installing to staging directory"),
+            PackageStep::InstallingTarget => write!(f, "Installing to target"),
+            PackageStep::InstallingImages => write!(f, "Installing to images directory"),
+            PackageStep::Other(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// Progress of a build, parsed from the `>>>` messages of Buildroot
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// Name of the package, if the step is about a package
+    pub package: Option<String>,
+    /// Version of the package, if any
+    pub version: Option<String>,
+    /// Current step
+    pub step: PackageStep,
+}
+
+impl Progress {
+    /// Parse a line of the build output, like `>>> foo 1.2.3 Configuring`
+    pub fn parse(line: &str) -> Option<Self> {
+        lazy_static! {
+            static ref ESCAPE: Regex = Regex::new(r"\x1b(\[[0-9;]*[A-Za-z]|\(B)").unwrap();
+        }
+
+        let line = ESCAPE.replace_all(line, "");
+        let message = line.strip_prefix(">>> ")?.trim_end();
+        // Messages not related to a package have empty name and version.
+        if let Some(step) = message.strip_prefix("  ") {
+            return Some(Self {
+                package: None,
+                version: None,
+                step: PackageStep::from(step.trim_start()),
+            });
+        }
+        let (package, rest) = message.split_once(' ')?;
+        let (version, step) = rest.split_once(' ')?;
+        Some(Self {
+            package: Some(package.to_string()),
+            version: (!version.is_empty()).then(|| version.to_string()),
+            step: PackageStep::from(step.trim_start()),
+        })
+    }
+}
+
+/// Event reported while building
+#[derive(Debug)]
+pub enum BuildEvent<'a> {
+    /// Line printed by make
+    Output(Stream, &'a str),
+    /// Progress of the build
+    Progress(Progress),
+}
+
 impl Builder {
+    /// Return the version of the main tree when the builder was created
+    pub fn recorded_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Return the current version of the main tree
+    pub fn current_version(&self) -> Option<BuildrootVersion> {
+        BuildrootVersion::from_tree(&self.main).ok()
+    }
+
+    /// Check if the main tree changed version since the builder was created
+    pub fn is_outdated(&self) -> bool {
+        match (self.recorded_version(), self.current_version()) {
+            (Some(recorded), Some(current)) => recorded != current.to_string(),
+            _ => false,
+        }
+    }
+
+    /// Return the options of the make invocation
+    pub fn options(&self) -> &MakeOptions {
+        &self.options
+    }
+
+    /// Return the options of the make invocation, for modification
+    pub fn options_mut(&mut self) -> &mut MakeOptions {
+        &mut self.options
+    }
+
+    /// Return the config fragments merged into the defconfig
+    pub fn fragments(&self) -> &[PathBuf] {
+        &self.fragments
+    }
+
+    /// Add a config fragment, merged into the defconfig after the others
+    pub fn add_fragment<P: AsRef<Path>>(&mut self, path: P) {
+        self.fragments.push(path.as_ref().to_path_buf());
+    }
+
+    /// Return the symbols overriding the ones of the defconfig and fragments
+    pub fn symbols(&self) -> &BTreeMap<String, String> {
+        &self.symbols
+    }
+
+    /// Override the value of a symbol, like `BR2_ENABLE_DEBUG` and `y`
+    pub fn set_symbol(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let symbol = format!("{name}={value}").parse::<Symbol>()?;
+        if symbol.name != name {
+            return Err(defconfig::Error::InvalidSymbol(name.to_string()).into());
+        }
+        self.symbols.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Return the path to the defconfig used by Buildroot
+    ///
+    /// When fragments or symbols are set, this is a defconfig generated in
+    /// the output directory, which is also where `savedefconfig` writes.
+    pub fn defconfig_path(&self) -> PathBuf {
+        if self.fragments.is_empty() && self.symbols.is_empty() {
+            self.defconfig.clone()
+        } else {
+            self.output.join(GENERATED_DEFCONFIG)
+        }
+    }
+
+    /// Generate the defconfig, merging the fragments and symbols into the
+    /// defconfig of the builder, if needed
+    ///
+    /// Like `merge_config.sh`, the defconfig and the fragments are copied as
+    /// is, followed by the symbols, and the last value of a symbol wins.
+    pub fn write_defconfig(&self) -> Result<PathBuf, Error> {
+        let path = self.defconfig_path();
+        if let Some(text) = self.generated_defconfig()? {
+            std::fs::create_dir_all(&self.output)?;
+            std::fs::write(&path, text)?;
+        }
+        Ok(path)
+    }
+
+    /// Return the contents of the generated defconfig, if fragments or
+    /// symbols are set, without writing it
+    pub fn generated_defconfig(&self) -> Result<Option<String>, Error> {
+        if self.defconfig_path() == self.defconfig {
+            return Ok(None);
+        }
+        let mut text = String::new();
+        for source in std::iter::once(&self.defconfig).chain(&self.fragments) {
+            let contents = std::fs::read_to_string(source)?;
+            text.push_str(&format!("# {}\n{contents}", source.display()));
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        if !self.symbols.is_empty() {
+            text.push_str("# Symbols of the build definition\n");
+        }
+        for (name, value) in &self.symbols {
+            text.push_str(&format!("{name}={value}\n"));
+        }
+        Ok(Some(text))
+    }
+
+    /// Return the logs of the builds
+    pub fn logs(&self) -> BuildLogs {
+        self.logs
+            .clone()
+            .unwrap_or_else(|| BuildLogs::new(self.output.join("logs"), logs::DEFAULT_KEEP))
+    }
+
+    /// Set the location of the logs of the builds
+    pub fn set_logs(&mut self, logs: BuildLogs) {
+        self.logs = Some(logs);
+    }
+
+    /// Set whether the output of the builds is left on the terminal instead
+    /// of being captured into logs
+    pub fn set_no_log(&mut self, no_log: bool) {
+        self.no_log = no_log;
+    }
+
+    fn create_log(&self) -> Result<Option<BuildLog>, Error> {
+        if self.no_log {
+            return Ok(None);
+        }
+        Ok(Some(self.logs().create()?))
+    }
+
+    /// Explore the main and external trees of the builder
+    pub fn explore(&self) -> Result<Buildroot, Error> {
+        let mut explorer = BuildrootExplorer::new(&self.main);
+        for external in &self.externals {
+            explorer.external_tree(external);
+        }
+        let buildroot = explorer.explore().map_err(Box::new)?;
+        Ok(buildroot)
+    }
+
+    /// Perform an action on a package, like `foo-rebuild`
+    ///
+    /// The package must be defined in one of the trees. A host package must
+    /// be provided by its target package, using a `host-` infrastructure.
+    pub fn run_package_action(&self, package: &str, action: PackageAction) -> Result<(), Error> {
+        self.check_package(package)?;
+        self.build_targets(&[action.target(package)])
+    }
+
+    fn check_package(&self, package: &str) -> Result<(), Error> {
+        let buildroot = self.explore()?;
+        if buildroot.packages().any(|(n, _)| n == package) {
+            return Ok(());
+        }
+        let is_host = package
+            .strip_prefix("host-")
+            .and_then(|name| buildroot.package_info(name).ok())
+            .is_some_and(|info| {
+                info.infrastructures()
+                    .iter()
+                    .any(|i| i.starts_with("host-"))
+            });
+        if !is_host {
+            return Err(Error::UnknownPackage(package.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Return the path to the file overriding the source directories of
+    /// packages, set by `BR2_PACKAGE_OVERRIDE_FILE`
+    ///
+    /// `$(CONFIG_DIR)`, `$(TOPDIR)` and the `$(BR2_EXTERNAL_<NAME>_PATH)`
+    /// variables of the external trees are expanded, other variables are
+    /// rejected.
+    pub fn override_file(&self) -> Result<PathBuf, Error> {
+        let value = Defconfig::from_path(self.output.join(".config"))
+            .ok()
+            .and_then(|d| {
+                d.symbols()
+                    .iter()
+                    .find(|s| s.name == "BR2_PACKAGE_OVERRIDE_FILE")
+                    .map(|s| s.value.clone())
+            });
+        let value = match value {
+            Some(SymbolValue::String(value)) if !value.is_empty() => value,
+            _ => return Ok(self.output.join("local.mk")),
+        };
+        let mut value = value
+            .replace("$(CONFIG_DIR)", &self.output.to_string_lossy())
+            .replace("$(TOPDIR)", &self.main.to_string_lossy());
+        if value.contains("$(BR2_EXTERNAL_") {
+            for external in self.explore()?.trees().filter_map(|t| t.external()) {
+                value = value.replace(
+                    &format!("$({})", external.path_variable()),
+                    &external.path().to_string_lossy(),
+                );
+            }
+        }
+        if value.contains('$') {
+            return Err(Error::UnsupportedOverrideFile(value));
+        }
+        // Make runs from the main tree.
+        Ok(self.main.join(value))
+    }
+
+    /// Return the source directories overriding the ones of packages
+    pub fn overrides(&self) -> Result<BTreeMap<String, PathBuf>, Error> {
+        let file = OverrideFile::from_path(self.override_file()?)?;
+        let buildroot = self.explore()?;
+        Ok(file.overrides(buildroot.packages().map(|(n, _)| n.as_str())))
+    }
+
+    /// Build `package` from the source directory at `path`
+    pub fn set_override<P: AsRef<Path>>(&self, package: &str, path: P) -> Result<(), Error> {
+        self.check_package(package)?;
+        let mut file = OverrideFile::from_path(self.override_file()?)?;
+        file.set(package, path)?;
+        file.save()?;
+        Ok(())
+    }
+
+    /// Build `package` from its regular sources again, returning whether its
+    /// source directory was overridden
+    pub fn remove_override(&self, package: &str) -> Result<bool, Error> {
+        let mut file = OverrideFile::from_path(self.override_file()?)?;
+        let removed = file.remove(package);
+        if removed {
+            file.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Return the packages enabled by the configuration of the output directory
+    pub fn packages(&self) -> Result<Vec<String>, Error> {
+        let mut cmd = self.command(&["show-targets"])?;
+        // Errors of make are reported in the failure, not on the terminal.
+        let output = cmd
+            .arg("-s")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let lines: Vec<&str> = stderr.lines().collect();
+            return Err(Error::BuildFailed(Box::new(BuildFailure {
+                target: "show-targets".to_string(),
+                exit_code: output.status.code(),
+                log_excerpt: excerpt(&lines),
+                ..Default::default()
+            })));
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        let packages = text
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter(|t| !t.starts_with("rootfs-"))
+            .map(String::from)
+            .collect();
+        Ok(packages)
+    }
+
     /// Run a build step
+    ///
+    /// Unless disabled, the output of the build is also written to a new log
+    /// file.
     pub fn run_step(&self, step: BuildStep) -> Result<(), Error> {
-        let mut targets = vec![];
-        match step {
-            BuildStep::Init => targets.push("defconfig"),
-            BuildStep::All => targets.extend_from_slice(&["defconfig", "all"]),
-            BuildStep::Main => targets.push("all"),
+        self.run_step_with(step, print_output)
+    }
+
+    /// Run a build step, passing the output and progress of the build to
+    /// `on_event` instead of printing them
+    pub fn run_step_with<F>(&self, step: BuildStep, on_event: F) -> Result<(), Error>
+    where
+        F: FnMut(BuildEvent) + Send,
+    {
+        let log = self.create_log()?;
+        let on_event = Mutex::new(on_event);
+        let mut res = Ok(());
+        for target in step_targets(step) {
+            res = self.run_logged(&[target], log.as_ref(), &on_event);
+            if res.is_err() {
+                break;
+            }
         }
-        // "defconfig" can not be batched with "all", so build each separately.
-        for target in targets {
-            self.build_targets(&[target])?;
+        finish_log(log, res)
+    }
+
+    /// Return the commands run by a build step, without running them
+    ///
+    /// The generated defconfig, if any, is not written: see
+    /// [`Builder::generated_defconfig()`].
+    pub fn step_commands(&self, step: BuildStep) -> Result<Vec<Command>, Error> {
+        step_targets(step)
+            .iter()
+            .map(|t| self.command(&[t]))
+            .collect()
+    }
+
+    /// Return the POSIX shell script building `targets`, without running it
+    ///
+    /// When the targets include "defconfig", the script starts by writing
+    /// the generated defconfig, if any, like when building.
+    pub fn dry_run_script<S: AsRef<str>>(&self, targets: &[S]) -> Result<Vec<String>, Error> {
+        let mut script = vec![];
+        if targets.iter().any(|t| t.as_ref() == "defconfig") {
+            if let Some(text) = self.generated_defconfig()? {
+                script.push(write_file_command(&self.defconfig_path(), &text));
+            }
         }
-        Ok(())
+        script.push(shell_command(&self.command(targets)?));
+        Ok(script)
+    }
+
+    /// Return the POSIX shell script run by a build step, without running it
+    pub fn step_dry_run_script(&self, step: BuildStep) -> Result<Vec<String>, Error> {
+        let mut script = vec![];
+        for target in step_targets(step) {
+            script.extend(self.dry_run_script(&[target])?);
+        }
+        Ok(script)
     }
 
     /// Build a list of targets specified by name
+    ///
+    /// Unless disabled, the output of the build is also written to a new log
+    /// file. Interactive targets, like `menuconfig` or `linux-nconfig`, are
+    /// left on the terminal.
     pub fn build_targets<S: AsRef<str>>(&self, targets: &[S]) -> Result<(), Error> {
-        let mut cmd = std::process::Command::new("make");
-        let external: String = self.externals.iter().fold(String::new(), |a, p| {
-            a + ":" + &p.as_os_str().to_string_lossy()
+        self.build_targets_with(targets, print_output)
+    }
+
+    /// Build a list of targets specified by name, passing the output and
+    /// progress of the build to `on_event` instead of printing them
+    pub fn build_targets_with<S, F>(&self, targets: &[S], on_event: F) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        F: FnMut(BuildEvent) + Send,
+    {
+        let log = self.create_log()?;
+        let res = self.run_logged(targets, log.as_ref(), &Mutex::new(on_event));
+        finish_log(log, res)
+    }
+
+    fn run_logged<S, F>(
+        &self,
+        targets: &[S],
+        log: Option<&BuildLog>,
+        on_event: &Mutex<F>,
+    ) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        F: FnMut(BuildEvent) + Send,
+    {
+        // Other targets, like "savedefconfig", must not overwrite the
+        // generated defconfig.
+        if targets.iter().any(|t| t.as_ref() == "defconfig") {
+            self.write_defconfig()?;
+        }
+        let mut cmd = self.command(targets)?;
+        let target = targets
+            .iter()
+            .map(|t| t.as_ref())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if let Some(log) = log {
+            log.write_line(Stream::Stdout, &format!("$ {cmd:?}"))?;
+        }
+        let log = match log {
+            Some(log) if !targets.iter().any(|t| is_interactive(t.as_ref())) => log,
+            _ => {
+                // Interactive targets need the terminal, so the output is not
+                // captured.
+                let status = cmd.status()?;
+                if let Some(log) = log {
+                    log.write_line(Stream::Stdout, &format!("make {status}"))?;
+                }
+                if status.success() {
+                    return Ok(());
+                }
+                return Err(Error::BuildFailed(Box::new(BuildFailure {
+                    target,
+                    exit_code: status.code(),
+                    ..Default::default()
+                })));
+            }
+        };
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let failure = Mutex::new(BuildFailure {
+            target,
+            ..Default::default()
+        });
+        let lines = Mutex::new(VecDeque::with_capacity(EXCERPT_LINES));
+        let on_line = |stream: Stream, line: &str| {
+            let mut on_event = on_event.lock().unwrap_or_else(|e| e.into_inner());
+            let mut lines = lines.lock().unwrap_or_else(|e| e.into_inner());
+            if lines.len() == EXCERPT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+            on_event(BuildEvent::Output(stream, line));
+            if let Some(progress) = Progress::parse(line) {
+                let mut failure = failure.lock().unwrap_or_else(|e| e.into_inner());
+                failure.package = progress.package.clone();
+                failure.step = Some(progress.step.clone());
+                on_event(BuildEvent::Progress(progress));
+            }
+        };
+        // Copy both streams concurrently, so make never blocks on a full pipe.
+        let (out, err) = std::thread::scope(|s| {
+            let out = s.spawn(|| match stdout {
+                Some(r) => log.tee(r, Stream::Stdout, |l| on_line(Stream::Stdout, l)),
+                None => Ok(()),
+            });
+            let err = s.spawn(|| match stderr {
+                Some(r) => log.tee(r, Stream::Stderr, |l| on_line(Stream::Stderr, l)),
+                None => Ok(()),
+            });
+            (out.join(), err.join())
         });
-        if !external[1..].is_empty() {
-            cmd.arg(format!("BR2_EXTERNAL={}", &external[1..]));
+        let status = child.wait()?;
+        for res in [out, err] {
+            res.unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+        }
+        log.write_line(Stream::Stdout, &format!("make {status}"))?;
+        if status.success() {
+            return Ok(());
+        }
+        let mut failure = failure.into_inner().unwrap_or_else(|e| e.into_inner());
+        failure.exit_code = status.code();
+        failure.log_excerpt = lines.into_inner().unwrap_or_else(|e| e.into_inner()).into();
+        Err(Error::BuildFailed(Box::new(failure)))
+    }
+
+    /// Return the command building a list of targets specified by name
+    pub fn command<S: AsRef<str>>(&self, targets: &[S]) -> Result<Command, Error> {
+        let mut cmd = Command::new(self.options.make.as_deref().unwrap_or(Path::new("make")));
+        if !self.externals.is_empty() {
+            // Buildroot splits BR2_EXTERNAL on colons and spaces.
+            let externals = self
+                .externals
+                .iter()
+                .map(|p| match p.to_str() {
+                    Some(s) if !s.contains([':', ' ']) => Ok(s),
+                    _ => Err(Error::InvalidExternalPath(p.clone())),
+                })
+                .collect::<Result<Vec<&str>, Error>>()?;
+            cmd.arg(format!("BR2_EXTERNAL={}", externals.join(":")));
         }
-        let defconfig = format!("BR2_DEFCONFIG={}", self.defconfig.to_string_lossy());
-        let output = format!("O={}", self.output.to_string_lossy());
         cmd.arg("-C")
             .arg(self.main.as_os_str())
-            .arg(output)
-            .arg(defconfig);
+            .arg(path_variable("O", &self.output))
+            .arg(path_variable("BR2_DEFCONFIG", &self.defconfig_path()));
+        if let Some(jobs) = self.options.jobs {
+            cmd.arg(format!("BR2_JLEVEL={jobs}"));
+        }
+        for (name, value) in &self.options.variables {
+            cmd.arg(format!("{name}={value}"));
+        }
+        if let Some(ref dir) = self.options.dl_dir {
+            cmd.env("BR2_DL_DIR", dir);
+        }
+        if let Some(ref dir) = self.options.ccache_dir {
+            cmd.env("BR2_CCACHE_DIR", dir);
+        }
+        cmd.envs(&self.options.env);
         for target in targets {
             cmd.arg(target.as_ref());
         }
-        let status = cmd.status()?;
-        status.success().then_some(()).ok_or(Error::BuildFailed)
+        Ok(cmd)
     }
 
     /// Deserialize a builder from TOML
@@ -121,3 +916,433 @@ impl Builder {
         Ok(text)
     }
 }
+
+/// Close the log of a build, if any, recording its path in the failure.
+fn finish_log(log: Option<BuildLog>, res: Result<(), Error>) -> Result<(), Error> {
+    let Some(log) = log else {
+        return res;
+    };
+    let path = log.finish(res.is_ok())?;
+    match res {
+        Err(Error::BuildFailed(mut failure)) => {
+            failure.log = Some(path);
+            Err(Error::BuildFailed(failure))
+        }
+        res => res,
+    }
+}
+
+/// Check if `target` runs an interactive configuration tool, like
+/// `menuconfig` or `linux-nconfig`.
+fn is_interactive(target: &str) -> bool {
+    let tool = target.rsplit('-').next().unwrap_or(target);
+    [
+        "config",
+        "oldconfig",
+        "menuconfig",
+        "nconfig",
+        "xconfig",
+        "gconfig",
+    ]
+    .contains(&tool)
+}
+
+fn step_targets(step: BuildStep) -> &'static [&'static str] {
+    // "defconfig" can not be batched with "all", so build each separately.
+    match step {
+        BuildStep::Init => &["defconfig"],
+        BuildStep::All => &["defconfig", "all"],
+        BuildStep::Main => &["all"],
+    }
+}
+
+fn excerpt(lines: &[&str]) -> Vec<String> {
+    let start = lines.len().saturating_sub(EXCERPT_LINES);
+    lines[start..].iter().map(|l| l.to_string()).collect()
+}
+
+fn print_output(event: BuildEvent) {
+    // Failing to print must not abort the build, which is still logged.
+    let _ = match event {
+        BuildEvent::Output(Stream::Stdout, line) => writeln!(std::io::stdout(), "{line}"),
+        BuildEvent::Output(Stream::Stderr, line) => writeln!(std::io::stderr(), "{line}"),
+        BuildEvent::Progress(_) => Ok(()),
+    };
+}
+
+fn path_variable(name: &str, path: &Path) -> std::ffi::OsString {
+    let mut arg = std::ffi::OsString::from(format!("{name}="));
+    arg.push(path.as_os_str());
+    arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildroot::tests::{mock_external, mock_tree, BUILDROOT_TEST_DIR};
+
+    fn builder(externals: &[&str]) -> Builder {
+        Builder {
+            defconfig: PathBuf::from("/src/buildroot/configs/qemu_defconfig"),
+            output: PathBuf::from("/tmp/output"),
+            main: PathBuf::from("/src/buildroot"),
+            externals: externals.iter().map(PathBuf::from).collect(),
+            version: None,
+            fragments: vec![],
+            options: MakeOptions::default(),
+            symbols: BTreeMap::new(),
+            logs: None,
+            no_log: false,
+        }
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_progress() {
+        let progress = Progress::parse(">>> busybox 1.36.1 Configuring").unwrap();
+        assert_eq!(progress.package.as_deref(), Some("busybox"));
+        assert_eq!(progress.version.as_deref(), Some("1.36.1"));
+        assert_eq!(progress.step, PackageStep::Configuring);
+
+        let progress =
+            Progress::parse("\x1b[7m>>> host-skeleton  Installing to host directory\x1b[27m")
+                .unwrap();
+        assert_eq!(progress.package.as_deref(), Some("host-skeleton"));
+        assert_eq!(progress.version, None);
+        assert_eq!(progress.step, PackageStep::InstallingHost);
+        assert!(progress.step.is_install());
+
+        let progress = Progress::parse(">>>   Finalizing target directory").unwrap();
+        assert_eq!(progress.package, None);
+        assert_eq!(
+            progress.step,
+            PackageStep::Other("Finalizing target directory".to_string())
+        );
+        assert_eq!(progress.step.to_string(), "Finalizing target directory");
+
+        assert_eq!(Progress::parse("make: Entering directory"), None);
+        assert_eq!(Progress::parse(">>> foo"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn report_build_failure() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let make = dir.path().join("make");
+        let script = "#!/bin/sh\necho '>>> foo 1.0 Building'\necho 'error: boom' >&2\nexit 3\n";
+        std::fs::write(&make, script).unwrap();
+        std::fs::set_permissions(&make, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut builder = builder(&[]);
+        builder.options_mut().make = Some(make);
+        builder.set_logs(BuildLogs::new(dir.path().join("logs"), 1));
+
+        let mut events = vec![];
+        let res = builder.run_step_with(BuildStep::Main, |e| {
+            if let BuildEvent::Progress(p) = e {
+                events.push(p);
+            }
+        });
+        let Err(Error::BuildFailed(failure)) = res else {
+            panic!("unexpected result: {res:?}");
+        };
+        assert_eq!(events.len(), 1);
+        assert_eq!(failure.target, "all");
+        assert_eq!(failure.package.as_deref(), Some("foo"));
+        assert_eq!(failure.step, Some(PackageStep::Building));
+        assert_eq!(failure.exit_code, Some(3));
+        assert_eq!(failure.log_excerpt.len(), 2);
+        assert!(failure.log_excerpt.contains(&"error: boom".to_string()));
+        assert_eq!(failure.log, Some(builder.logs().latest(true).unwrap().path));
+        assert_eq!(
+            failure.to_string(),
+            "make all, package foo, step \"Building\", exit code 3"
+        );
+        assert_eq!(failure.status_code(), Some(3));
+        let err = crate::mason::Error::Builder(Error::BuildFailed(failure.clone()));
+        assert_eq!(BuildFailure::find(&err), Some(failure.as_ref()));
+        assert_eq!(BuildFailure::find(&Error::InvalidStep), None);
+
+        let Err(Error::BuildFailed(failure)) = builder.packages() else {
+            panic!("show-targets did not fail");
+        };
+        assert_eq!(failure.target, "show-targets");
+        assert_eq!(failure.log_excerpt, ["error: boom"]);
+    }
+
+    #[test]
+    fn parse_package_action() {
+        for name in [
+            "rebuild",
+            "reconfigure",
+            "reinstall",
+            "dirclean",
+            "source",
+            "extract",
+            "legal-info",
+        ] {
+            let action = PackageAction::from_str(name).unwrap();
+            assert_eq!(action.to_string(), name);
+        }
+        assert_eq!(PackageAction::LegalInfo.target("foo"), "foo-legal-info");
+        assert!(matches!(
+            PackageAction::from_str("rebiuld"),
+            Err(Error::InvalidPackageAction(_))
+        ));
+    }
+
+    #[test]
+    fn merge_fragments_and_symbols() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("qemu_defconfig");
+        let fragment = dir.path().join("debug.fragment");
+        std::fs::write(&base, "BR2_x86_64=y\nBR2_PACKAGE_FOO=y\n").unwrap();
+        std::fs::write(&fragment, "BR2_ENABLE_DEBUG=y\nBR2_PACKAGE_FOO=n\n").unwrap();
+        let mut builder = builder(&[]);
+        builder.defconfig = base.clone();
+        builder.output = dir.path().join("output");
+        assert_eq!(builder.write_defconfig().unwrap(), base);
+        assert_eq!(builder.generated_defconfig().unwrap(), None);
+
+        builder.add_fragment(&fragment);
+        builder
+            .set_symbol("BR2_TARGET_GENERIC_HOSTNAME", "\"qemu\"")
+            .unwrap();
+        assert!(builder.set_symbol("ENABLE_DEBUG", "y").is_err());
+        assert!(builder.set_symbol("BR2_ENABLE_DEBUG", "yes").is_err());
+        let path = builder.write_defconfig().unwrap();
+        assert_eq!(path, builder.output.join(GENERATED_DEFCONFIG));
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(Some(&text), builder.generated_defconfig().unwrap().as_ref());
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with("# ")).collect();
+        assert_eq!(
+            lines,
+            [
+                "BR2_x86_64=y",
+                "BR2_PACKAGE_FOO=y",
+                "BR2_ENABLE_DEBUG=y",
+                "BR2_PACKAGE_FOO=n",
+                "BR2_TARGET_GENERIC_HOSTNAME=\"qemu\""
+            ]
+        );
+        let defconfig = Defconfig::from_path(&path).unwrap();
+        assert!(!defconfig.selects("foo"));
+        let cmd = builder.command(&["defconfig"]).unwrap();
+        let arg = format!("BR2_DEFCONFIG={}", path.display());
+        assert!(args(&cmd).contains(&arg));
+
+        let script = builder.step_dry_run_script(BuildStep::All).unwrap();
+        assert_eq!(script.len(), 3);
+        assert!(script[0].contains(&format!("cat > {}", path.display())));
+        assert!(script[1].ends_with(" defconfig") && script[2].ends_with(" all"));
+        assert_eq!(
+            builder.step_dry_run_script(BuildStep::Main).unwrap().len(),
+            1
+        );
+        let script = builder.dry_run_script(&["linux-rebuild"]).unwrap();
+        assert_eq!(script.len(), 1);
+
+        let loaded = Builder::from_toml(&builder.to_toml().unwrap()).unwrap();
+        assert_eq!(loaded.fragments(), [fragment]);
+        assert_eq!(loaded.symbols(), builder.symbols());
+    }
+
+    #[test]
+    fn detect_interactive_targets() {
+        for target in [
+            "menuconfig",
+            "nconfig",
+            "linux-menuconfig",
+            "busybox-menuconfig",
+        ] {
+            assert!(is_interactive(target), "{target}");
+        }
+        for target in [
+            "all",
+            "defconfig",
+            "savedefconfig",
+            "linux-rebuild",
+            "source",
+        ] {
+            assert!(!is_interactive(target), "{target}");
+        }
+    }
+
+    #[test]
+    fn command_without_external() {
+        let cmd = builder(&[]).command(&["all"]).unwrap();
+        assert_eq!(cmd.get_program(), "make");
+        assert_eq!(
+            args(&cmd),
+            [
+                "-C",
+                "/src/buildroot",
+                "O=/tmp/output",
+                "BR2_DEFCONFIG=/src/buildroot/configs/qemu_defconfig",
+                "all"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_with_externals() {
+        let cmd = builder(&["/src/acme"]).command(&["defconfig"]).unwrap();
+        assert_eq!(args(&cmd)[0], "BR2_EXTERNAL=/src/acme");
+        let cmd = builder(&["/src/acme", "/src/frob"])
+            .command(&["linux-rebuild", "all"])
+            .unwrap();
+        let args = args(&cmd);
+        assert_eq!(args[0], "BR2_EXTERNAL=/src/acme:/src/frob");
+        assert_eq!(args[args.len() - 2..], ["linux-rebuild", "all"]);
+    }
+
+    #[test]
+    fn command_with_invalid_externals() {
+        for path in ["/src/my acme", "/src/acme:v2"] {
+            let res = builder(&["/src/frob", path]).command(&["all"]);
+            assert!(matches!(res, Err(Error::InvalidExternalPath(p)) if p == Path::new(path)));
+        }
+    }
+
+    #[test]
+    fn format_step_commands() {
+        let mut builder = builder(&[]);
+        builder.output = PathBuf::from("/tmp/my output");
+        let options = builder.options_mut();
+        options
+            .env
+            .insert("CFLAGS".to_string(), "-O2 -g".to_string());
+        options
+            .variables
+            .insert("FOO".to_string(), "it's".to_string());
+        let cmds = builder.step_commands(BuildStep::All).unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(
+            shell_command(&cmds[0]),
+            "CFLAGS='-O2 -g' make -C /src/buildroot 'O=/tmp/my output' \
+             BR2_DEFCONFIG=/src/buildroot/configs/qemu_defconfig 'FOO=it'\\''s' defconfig"
+        );
+        assert!(shell_command(&cmds[1]).ends_with(" all"));
+        assert_eq!(builder.step_commands(BuildStep::Main).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn command_with_options() {
+        let mut builder = builder(&[]);
+        let options = builder.options_mut();
+        options.make = Some(PathBuf::from("/usr/bin/gmake"));
+        options.jobs = Some(8);
+        options.dl_dir = Some(PathBuf::from("/var/cache/dl"));
+        options.variables.insert("V".to_string(), "1".to_string());
+        options.env.insert("LC_ALL".to_string(), "C".to_string());
+        let cmd = builder.command(&["all"]).unwrap();
+        assert_eq!(cmd.get_program(), "/usr/bin/gmake");
+        let args = args(&cmd);
+        assert_eq!(args[args.len() - 3..], ["BR2_JLEVEL=8", "V=1", "all"]);
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&("BR2_DL_DIR".as_ref(), Some("/var/cache/dl".as_ref()))));
+        assert!(envs.contains(&("LC_ALL".as_ref(), Some("C".as_ref()))));
+
+        let text = builder.to_toml().unwrap();
+        let loaded = Builder::from_toml(&text).unwrap();
+        assert_eq!(loaded.options(), builder.options());
+        let mut overrides = MakeOptions {
+            jobs: Some(2),
+            ..Default::default()
+        };
+        overrides.variables.insert("V".to_string(), "0".to_string());
+        builder.options_mut().merge(&overrides);
+        assert_eq!(builder.options().jobs, Some(2));
+        assert_eq!(builder.options().variables["V"], "0");
+        assert!(builder.options().dl_dir.is_some());
+    }
+
+    #[test]
+    fn run_package_action() {
+        let path = tempfile::Builder::new()
+            .prefix(BUILDROOT_TEST_DIR)
+            .tempdir()
+            .unwrap();
+        mock_tree(&path).unwrap();
+        let bar = path.path().join("package").join("bar").join("bar.mk");
+        let mut contents = std::fs::read_to_string(&bar).unwrap();
+        contents.push_str("$(eval $(host-generic-package))\n");
+        std::fs::write(&bar, contents).unwrap();
+        let output = path.path().join("output");
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let mut builder = buildroot
+            .create_builder("acme_quux_defconfig", &output)
+            .unwrap();
+        builder.options_mut().make = Some(PathBuf::from("true"));
+        for package in ["foo", "host-bar"] {
+            let res = builder.run_package_action(package, PackageAction::Rebuild);
+            assert!(res.is_ok());
+        }
+        for package in ["fo", "host-foo"] {
+            let res = builder.run_package_action(package, PackageAction::Dirclean);
+            assert!(matches!(res, Err(Error::UnknownPackage(p)) if p == package));
+        }
+    }
+
+    #[test]
+    fn manage_overrides() {
+        let path = tempfile::Builder::new()
+            .prefix(BUILDROOT_TEST_DIR)
+            .tempdir()
+            .unwrap();
+        mock_tree(&path).unwrap();
+        let ext = path.path().join("ext");
+        std::fs::create_dir(&ext).unwrap();
+        mock_external(&ext, "ACME").unwrap();
+        let output = path.path().join("output");
+        let src = path.path().join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        let mut explorer = BuildrootExplorer::new(&path);
+        explorer.external_tree(&ext);
+        let buildroot = explorer.explore().unwrap();
+        let builder = buildroot
+            .create_builder("acme_quux_defconfig", &output)
+            .unwrap();
+        assert_eq!(builder.override_file().unwrap(), output.join("local.mk"));
+        assert!(builder.overrides().unwrap().is_empty());
+
+        builder.set_override("foo", &src).unwrap();
+        let res = builder.set_override("qux", &src);
+        assert!(matches!(res, Err(Error::UnknownPackage(_))));
+        let overrides = builder.overrides().unwrap();
+        assert_eq!(overrides["foo"], std::fs::canonicalize(&src).unwrap());
+        assert!(builder.remove_override("foo").unwrap());
+        assert!(!builder.remove_override("foo").unwrap());
+
+        std::fs::write(
+            output.join(".config"),
+            "BR2_PACKAGE_OVERRIDE_FILE=\"$(CONFIG_DIR)/../overrides.mk\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            builder.override_file().unwrap(),
+            output.join("..").join("overrides.mk")
+        );
+        std::fs::write(
+            output.join(".config"),
+            "BR2_PACKAGE_OVERRIDE_FILE=\"$(BR2_EXTERNAL_ACME_PATH)/local.mk\"\n",
+        )
+        .unwrap();
+        assert_eq!(builder.override_file().unwrap(), ext.join("local.mk"));
+        std::fs::write(
+            output.join(".config"),
+            "BR2_PACKAGE_OVERRIDE_FILE=\"$(BASE_DIR)/local.mk\"\n",
+        )
+        .unwrap();
+        let res = builder.override_file();
+        assert!(
+            matches!(res, Err(Error::UnsupportedOverrideFile(v)) if v == "$(BASE_DIR)/local.mk")
+        );
+    }
+}
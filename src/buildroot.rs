@@ -6,18 +6,27 @@
 // SPDX-License-Identifier: MIT
 //
 
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 use thiserror::Error;
 use walkdir::{DirEntry, WalkDir};
 
 use super::{
+    board::{self, Board},
     builder::{self, BuildStep, Builder},
-    defconfig::{self, Defconfig},
-    package,
+    cache::{Cache, DirectoryIndex, FileStamp, TreeIndex},
+    defconfig::{self, Defconfig, SymbolValue},
+    legal::{self, LicenseEntry, LicenseReport},
+    package::{self, HashEntry, PackageConfig, PackageInfo},
+    pool,
+    version::{self, BuildrootVersion},
 };
 
 const BUILDROOT_SUBDIRS: [&str; 8] = [
@@ -40,10 +49,16 @@ pub enum Error {
     Defconfig(#[from] defconfig::Error),
     #[error("Directory traversal error: {0}")]
     DirectoryTraversal(#[from] walkdir::Error),
+    #[error("Duplicate external tree name: {0}")]
+    DuplicateExternalTree(String),
     #[error("Invalid external tree manifest: {0:?}")]
     InvalidExternalTreeManifest(PathBuf),
+    #[error("Invalid external tree name: {0:?}")]
+    InvalidExternalTreeName(String),
     #[error("Invalid Buildroot tree: {0:?}")]
     InvalidBuildrootTree(PathBuf),
+    #[error("Invalid output directory: {0:?}")]
+    InvalidOutputDirectory(PathBuf),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Package error: {0}")]
@@ -52,52 +67,88 @@ pub enum Error {
     UnknownDefconfig(String),
     #[error("Unknown package: {0}")]
     UnknownPackage(String),
+    #[error("Version error: {0}")]
+    Version(#[from] version::Error),
 }
 
-/// Information about a Buidlroot external tree.
-#[derive(Debug, Default)]
-struct ExternalTreeInfo {
+/// Information about a Buildroot external tree, from its `external.desc` file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExternalTree {
     name: String,
-    desc: String,
+    description: String,
+    path: PathBuf,
 }
 
-impl ExternalTreeInfo {
-    /// Build information about Buildroot external tree from `external.desc` file.
-    fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let contents = fs::read_to_string(&path)?;
-        let mut info = ExternalTreeInfo::default();
+impl ExternalTree {
+    /// Build information about the Buildroot external tree located at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let desc_path = path.as_ref().join("external.desc");
+        let contents = fs::read_to_string(&desc_path)?;
+        let (name, description) =
+            Self::parse_desc(&contents).ok_or(Error::InvalidExternalTreeManifest(desc_path))?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::InvalidExternalTreeName(name));
+        }
+        Ok(Self {
+            name,
+            description,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Extract the name and the description from the contents of an
+    /// `external.desc` file, ignoring unknown keys like Buildroot does.
+    ///
+    /// The name [`MAIN_TREE_NAME`] is rejected, as it designates the main
+    /// tree.
+    fn parse_desc(contents: &str) -> Option<(String, String)> {
+        let mut name = None;
+        let mut description = String::new();
         for line in contents.lines() {
-            let fields = line.split(':').collect::<Vec<&str>>();
-            if fields.len() != 2 {
-                return Err(Error::InvalidExternalTreeManifest(
-                    path.as_ref().to_path_buf(),
-                ));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            let value = fields[1].trim().to_string();
-            match fields[0] {
-                "name" => {
-                    info.name = value;
-                }
-                "desc" => {
-                    info.desc = value;
-                }
-                _ => {
-                    return Err(Error::InvalidExternalTreeManifest(
-                        path.as_ref().to_path_buf(),
-                    ))
-                }
+            let (key, value) = line.split_once(':')?;
+            match key.trim() {
+                "name" => name = Some(value.trim().to_string()),
+                "desc" => description = value.trim().to_string(),
+                _ => {}
             }
         }
-        Ok(info)
+        name.filter(|n| n != MAIN_TREE_NAME)
+            .map(|n| (n, description))
+    }
+
+    /// Return the name of the external tree.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the description of the external tree.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Return the path to the external tree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the name of the variable set by Buildroot to the path of the
+    /// external tree, i.e. `BR2_EXTERNAL_<NAME>_PATH`.
+    pub fn path_variable(&self) -> String {
+        format!("BR2_EXTERNAL_{}_PATH", self.name)
     }
 }
 
 #[derive(Debug)]
 struct BuildrootBaseTree {
-    #[allow(dead_code)]
     path: PathBuf,
     defconfigs: HashMap<String, PathBuf>,
     packages: HashMap<String, PathBuf>,
+    /// Other makefiles of packages defined several times in the tree.
+    duplicates: HashMap<String, Vec<PathBuf>>,
 }
 
 fn is_defconfig(entry: &DirEntry) -> bool {
@@ -108,139 +159,515 @@ fn is_defconfig(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
-fn is_package(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|f| f.ends_with(".mk"))
-        .unwrap_or(false)
+/// Check if `entry` is the makefile of a package, i.e. `<name>/<name>.mk`
+/// evaluating a package infrastructure macro, like `$(eval $(generic-package))`.
+fn is_package(entry: &DirEntry) -> Result<bool, Error> {
+    lazy_static! {
+        static ref PACKAGE_MACRO: Regex =
+            Regex::new(r"(?m)^\s*\$\(eval\s+\$\((host-)?[a-z0-9-]*package\)\)").unwrap();
+    }
+
+    if !is_package_makefile(entry) {
+        return Ok(false);
+    }
+    let contents = fs::read_to_string(entry.path())?;
+    Ok(PACKAGE_MACRO.is_match(&contents))
+}
+
+/// Check if `entry` is named like a package makefile, i.e. `<name>/<name>.mk`.
+fn is_package_makefile(entry: &DirEntry) -> bool {
+    let path = entry.path();
+    if !entry.file_type().is_file() || path.extension().map(|e| e != "mk").unwrap_or(true) {
+        return false;
+    }
+    let dir_name = path.parent().and_then(|p| p.file_name());
+    dir_name.is_some() && dir_name == path.file_stem()
 }
 
 impl BuildrootBaseTree {
-    fn from_path<P: AsRef<Path>>(path: P) -> Result<BuildrootBaseTree, Error> {
+    fn from_path<P: AsRef<Path>>(
+        path: P,
+        cache: Option<&Cache>,
+    ) -> Result<BuildrootBaseTree, Error> {
         let path = path.as_ref();
-        let cfg_dir = path.join("configs");
-        let defconfigs = if cfg_dir.exists() {
-            BuildrootBaseTree::collect_defconfigs(&cfg_dir)?
-        } else {
-            HashMap::new()
-        };
-        let packages = BuildrootBaseTree::collect_packages(path.join("package"))?;
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        // The cache only speeds things up: ignore it if it can not be used.
+        let old_index = cache.and_then(|c| c.load(&key).ok().flatten());
+        let mut index = TreeIndex::new(&key);
+        for dir in ["configs", "package"] {
+            if path.join(dir).exists() {
+                Self::scan(path, dir, old_index.as_ref(), &mut index)?;
+            }
+        }
+        if let Some(cache) = cache {
+            cache.store(&index).ok();
+        }
+
+        let mut defconfigs = HashMap::new();
+        let mut packages: HashMap<String, PathBuf> = HashMap::new();
+        let mut duplicates: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        // Like Buildroot, which includes `package/*/*.mk`, prefer the
+        // shallowest makefiles when a package is defined several times.
+        let mut directories: Vec<_> = index.directories.iter().collect();
+        directories.sort_by_key(|(dir, _)| Path::new(dir).components().count());
+        for (dir, dir_index) in directories {
+            let dir_path = path.join(dir);
+            for item in &dir_index.items {
+                if dir.starts_with("configs") {
+                    defconfigs.insert(item.clone(), dir_path.join(item));
+                    continue;
+                }
+                let pkg_path = dir_path.join(format!("{}.mk", item));
+                match packages.entry(item.clone()) {
+                    // Keep the first definition, list the others.
+                    Entry::Occupied(e) => duplicates
+                        .entry(e.key().clone())
+                        .or_default()
+                        .push(pkg_path),
+                    Entry::Vacant(e) => {
+                        e.insert(pkg_path);
+                    }
+                }
+            }
+        }
         Ok(Self {
             path: path.to_path_buf(),
             defconfigs,
             packages,
+            duplicates,
         })
     }
 
-    fn collect_defconfigs<P: AsRef<Path>>(path: P) -> Result<HashMap<String, PathBuf>, Error> {
-        let mut defconfigs = HashMap::new();
-        for entry in WalkDir::new(path).into_iter() {
+    /// Scan the directory `dir` of the tree at `path` for defconfigs or
+    /// packages, reusing the items of `old_index` for unmodified directories.
+    fn scan(
+        path: &Path,
+        dir: &str,
+        old_index: Option<&TreeIndex>,
+        index: &mut TreeIndex,
+    ) -> Result<(), Error> {
+        let mut dirs = vec![];
+        let mut files: HashMap<String, Vec<DirEntry>> = HashMap::new();
+        for entry in WalkDir::new(path.join(dir)).sort_by_file_name() {
             let entry = entry?;
-            if is_defconfig(&entry) {
-                defconfigs.insert(
-                    entry.file_name().to_string_lossy().to_string(),
-                    entry.into_path(),
-                );
+            let rel_path = entry.path().strip_prefix(path).unwrap_or(entry.path());
+            if entry.file_type().is_dir() {
+                let key = rel_path.to_string_lossy().to_string();
+                dirs.push((key, entry.metadata()?.modified()?.into()));
+                continue;
             }
+            let key = rel_path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            files.entry(key).or_default().push(entry);
         }
-        Ok(defconfigs)
-    }
-
-    fn collect_packages<P: AsRef<Path>>(path: P) -> Result<HashMap<String, PathBuf>, Error> {
-        let mut packages = HashMap::new();
-        for entry in WalkDir::new(path).into_iter() {
-            let entry = entry?;
-            if is_package(&entry) {
-                let path = entry.into_path();
-                let name = path.file_stem().unwrap().to_string_lossy().to_string();
-                packages.insert(name, path);
+        let mut candidates = vec![];
+        for (key, mtime) in dirs {
+            let entries = files.remove(&key).unwrap_or_default();
+            let mut stamps = BTreeMap::new();
+            for entry in entries.iter().filter(|e| is_package_makefile(e)) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                stamps.insert(name, FileStamp::from_metadata(&entry.metadata()?)?);
+            }
+            let dir_index = match old_index.and_then(|i| i.get(&key, mtime, &stamps)) {
+                Some(d) => d.clone(),
+                None => {
+                    candidates.extend(entries.into_iter().map(|e| (key.clone(), e)));
+                    DirectoryIndex {
+                        mtime,
+                        items: vec![],
+                        files: stamps,
+                    }
+                }
+            };
+            index.directories.insert(key, dir_index);
+        }
+        // Checking packages requires reading their makefiles, so do it in parallel.
+        let items = pool::map(&candidates, |(_, entry)| -> Result<Option<String>, Error> {
+            let name = if dir == "configs" {
+                is_defconfig(entry).then(|| entry.file_name().to_string_lossy().to_string())
+            } else {
+                is_package(entry)?.then(|| {
+                    entry
+                        .path()
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+            };
+            Ok(name)
+        });
+        for ((key, _), item) in candidates.iter().zip(items) {
+            if let (Some(item), Some(dir_index)) = (item?, index.directories.get_mut(key)) {
+                dir_index.items.push(item);
             }
         }
-        Ok(packages)
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 enum BuildrootTree {
     Main(BuildrootBaseTree),
-    #[allow(unused)]
-    External(String, BuildrootBaseTree),
+    External(ExternalTree, BuildrootBaseTree),
 }
 
+/// Name given to the main tree when reporting the origin of an item.
+pub const MAIN_TREE_NAME: &str = "main";
+
 impl BuildrootTree {
-    fn from_path(path: &BuildrootTreePath) -> Result<BuildrootTree, Error> {
+    fn name(&self) -> &str {
+        match self {
+            BuildrootTree::Main(_) => MAIN_TREE_NAME,
+            BuildrootTree::External(e, _) => e.name(),
+        }
+    }
+
+    fn base(&self) -> &BuildrootBaseTree {
+        match self {
+            BuildrootTree::Main(t) => t,
+            BuildrootTree::External(_, t) => t,
+        }
+    }
+
+    fn from_path(path: &BuildrootTreePath, cache: Option<&Cache>) -> Result<BuildrootTree, Error> {
         match path {
-            BuildrootTreePath::Main(p) => BuildrootTree::main_from_path(p),
-            BuildrootTreePath::External(p) => BuildrootTree::external_from_path(p),
+            BuildrootTreePath::Main(p) => BuildrootTree::main_from_path(p, cache),
+            BuildrootTreePath::External(p) => BuildrootTree::external_from_path(p, cache),
         }
     }
 
-    fn external_from_path<P: AsRef<Path>>(path: P) -> Result<BuildrootTree, Error> {
-        let ext_info_path = path.as_ref().join("external.desc");
-        let ext_info = ExternalTreeInfo::from_path(ext_info_path)?;
-        let tree = BuildrootBaseTree::from_path(&path)?;
-        Ok(BuildrootTree::External(ext_info.name, tree))
+    fn external_from_path<P: AsRef<Path>>(
+        path: P,
+        cache: Option<&Cache>,
+    ) -> Result<BuildrootTree, Error> {
+        let external = ExternalTree::from_path(&path)?;
+        let tree = BuildrootBaseTree::from_path(&path, cache)?;
+        Ok(BuildrootTree::External(external, tree))
     }
 
-    fn main_from_path<P: AsRef<Path>>(path: P) -> Result<BuildrootTree, Error> {
+    fn main_from_path<P: AsRef<Path>>(
+        path: P,
+        cache: Option<&Cache>,
+    ) -> Result<BuildrootTree, Error> {
         if BUILDROOT_SUBDIRS
             .iter()
             .any(|d| !path.as_ref().join(d).is_dir())
         {
             return Err(Error::InvalidBuildrootTree(path.as_ref().to_path_buf()));
         }
-        let tree = BuildrootBaseTree::from_path(&path)?;
+        let tree = BuildrootBaseTree::from_path(&path, cache)?;
         Ok(BuildrootTree::Main(tree))
     }
 }
 
+/// Kind of a tree of a Buildroot environment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeKind {
+    /// The main Buildroot tree
+    Main,
+    /// An external tree
+    External,
+}
+
+/// Reference to a tree of a Buildroot environment.
+#[derive(Clone, Copy, Debug)]
+pub struct TreeRef<'a> {
+    tree: &'a BuildrootTree,
+}
+
+impl<'a> TreeRef<'a> {
+    /// Return the kind of the tree.
+    pub fn kind(&self) -> TreeKind {
+        match self.tree {
+            BuildrootTree::Main(_) => TreeKind::Main,
+            BuildrootTree::External(_, _) => TreeKind::External,
+        }
+    }
+
+    /// Return the name of the tree, [`MAIN_TREE_NAME`] for the main tree.
+    pub fn name(&self) -> &'a str {
+        self.tree.name()
+    }
+
+    /// Return the path to the tree.
+    pub fn path(&self) -> &'a Path {
+        &self.tree.base().path
+    }
+
+    /// Return information about the tree, if it is an external one.
+    pub fn external(&self) -> Option<&'a ExternalTree> {
+        match self.tree {
+            BuildrootTree::External(e, _) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Return an iterator over the name and the path of the defconfig files of the tree.
+    pub fn defconfigs(&self) -> impl Iterator<Item = (&'a String, &'a PathBuf)> {
+        self.tree.base().defconfigs.iter()
+    }
+
+    /// Return an iterator over the name and the path of the package files of
+    /// the tree, including the ones shadowed by later trees.
+    pub fn packages(&self) -> impl Iterator<Item = (&'a String, &'a PathBuf)> {
+        self.tree.base().packages.iter()
+    }
+
+    /// Check if the tree defines the defconfig named `name`.
+    pub fn has_defconfig(&self, name: &str) -> bool {
+        self.tree.base().defconfigs.contains_key(name)
+    }
+
+    /// Check if the tree defines the package named `name`.
+    pub fn has_package(&self, name: &str) -> bool {
+        self.tree.base().packages.contains_key(name)
+    }
+}
+
+/// Location of the definition of a package in a tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageOrigin<'a> {
+    /// Name of the tree defining the package.
+    pub tree: &'a str,
+    /// Path to the makefile of the package.
+    pub path: &'a Path,
+    /// Whether the definition is overridden by one from a later tree, or
+    /// ignored as another definition in the same tree.
+    pub shadowed: bool,
+}
+
 /// Represent a Buildroot environment, with all defconfigs and packages.
+///
+/// As Buildroot includes the makefiles of the main tree first, then the ones
+/// of the external trees in the order they were given, a package defined in
+/// several trees gets its variables from the last one. This definition is the
+/// effective one, and the others are reported as shadowed.
 #[derive(Debug)]
 pub struct Buildroot {
     trees: Vec<BuildrootTree>,
+    infos: Mutex<HashMap<String, Arc<PackageInfo>>>,
 }
 
 impl Buildroot {
+    /// Return an iterator over the trees, the main one first, then the
+    /// external ones in the order they were given.
+    pub fn trees(&self) -> impl Iterator<Item = TreeRef<'_>> {
+        self.trees.iter().map(|tree| TreeRef { tree })
+    }
+
+    /// Return the tree named `name`.
+    pub fn tree(&self, name: &str) -> Option<TreeRef<'_>> {
+        self.trees().find(|t| t.name() == name)
+    }
+
+    /// Return the tree providing the defconfig named `name`.
+    pub fn defconfig_tree(&self, name: &str) -> Option<TreeRef<'_>> {
+        self.trees().find(|t| t.has_defconfig(name))
+    }
+
     /// Return an iterator over the name and the path of defconfig files.
     pub fn defconfigs(&self) -> impl Iterator<Item = (&String, &PathBuf)> {
-        self.trees.iter().flat_map(|t| match t {
-            BuildrootTree::Main(t) => t.defconfigs.iter(),
-            BuildrootTree::External(_, t) => t.defconfigs.iter(),
-        })
+        self.trees.iter().flat_map(|t| t.base().defconfigs.iter())
     }
 
     /// Return an iterator over the name and the path of package files.
+    ///
+    /// Each package is only returned once, with the path of its effective
+    /// definition.
     pub fn packages(&self) -> impl Iterator<Item = (&String, &PathBuf)> {
-        self.trees.iter().flat_map(|t| match t {
-            BuildrootTree::Main(t) => t.packages.iter(),
-            BuildrootTree::External(_, t) => t.packages.iter(),
+        let mut seen = HashSet::new();
+        self.trees
+            .iter()
+            .rev()
+            .flat_map(|t| t.base().packages.iter())
+            .filter(move |(n, _)| seen.insert(*n))
+    }
+
+    /// Return the trees defining the package named `name`, in inclusion order.
+    pub fn package_origin(&self, name: &str) -> Vec<PackageOrigin<'_>> {
+        let mut origins: Vec<PackageOrigin> = self
+            .trees
+            .iter()
+            .flat_map(|t| {
+                let base = t.base();
+                let duplicates = base.duplicates.get(name).into_iter().flatten();
+                duplicates
+                    .chain(base.packages.get(name))
+                    .map(|p| PackageOrigin {
+                        tree: t.name(),
+                        path: p.as_path(),
+                        shadowed: true,
+                    })
+            })
+            .collect();
+        if let Some(last) = origins.last_mut() {
+            last.shadowed = false;
+        }
+        origins
+    }
+
+    /// Return the packages defined in more than one tree, or several times in
+    /// the same tree, with their origins.
+    pub fn duplicate_packages(&self) -> BTreeMap<&str, Vec<PackageOrigin<'_>>> {
+        self.packages()
+            .map(|(n, _)| (n.as_str(), self.package_origin(n)))
+            .filter(|(_, o)| o.len() > 1)
+            .collect()
+    }
+
+    /// Return the version of the main tree.
+    pub fn version(&self) -> Result<BuildrootVersion, Error> {
+        Ok(BuildrootVersion::from_tree(self.main_tree_path())?)
+    }
+
+    /// Return an iterator over the external trees, in the order they were given.
+    pub fn externals(&self) -> impl Iterator<Item = &ExternalTree> {
+        self.trees.iter().filter_map(|t| match t {
+            BuildrootTree::External(e, _) => Some(e),
+            _ => None,
         })
     }
 
+    /// Return information about the package named `name`.
+    pub fn package_info(&self, name: &str) -> Result<Arc<PackageInfo>, Error> {
+        self.memoised_info(name, self.package_path(name)?)
+    }
+
+    /// Return information about all the packages, sorted by name.
+    ///
+    /// The package files are parsed in parallel, and the results kept for
+    /// later calls.
+    pub fn package_infos(&self) -> Vec<(&String, Result<Arc<PackageInfo>, Error>)> {
+        let mut packages: Vec<(&String, &PathBuf)> = self.packages().collect();
+        packages.sort();
+        let infos = pool::map(&packages, |(n, p)| self.memoised_info(n, p));
+        packages.into_iter().map(|(n, _)| n).zip(infos).collect()
+    }
+
+    /// Return information about the package named `name`, parsing the file
+    /// at `path` only if not already done.
+    fn memoised_info(&self, name: &str, path: &Path) -> Result<Arc<PackageInfo>, Error> {
+        if let Some(info) = self.infos.lock().unwrap().get(name) {
+            return Ok(info.clone());
+        }
+        let info = Arc::new(PackageInfo::from_path(path)?);
+        self.infos
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), info.clone());
+        Ok(info)
+    }
+
+    /// Return the configuration entries of the package named `name`, from
+    /// the `Config.in` and `Config.in.host` files of its directory.
+    pub fn package_configs(&self, name: &str) -> Result<Vec<PackageConfig>, Error> {
+        let symbols = [
+            package::package_symbol(name),
+            package::package_symbol(&format!("host-{name}")),
+        ];
+        let dir = self.package_path(name)?.parent().unwrap_or(Path::new(""));
+        let mut configs = vec![];
+        for file in ["Config.in", "Config.in.host"] {
+            let path = dir.join(file);
+            if path.exists() {
+                let entries = PackageConfig::from_path(path)?;
+                configs.extend(entries.into_iter().filter(|e| symbols.contains(&e.symbol)));
+            }
+        }
+        Ok(configs)
+    }
+
+    /// Return the configuration entries of all the packages, sorted by name.
+    ///
+    /// The `Config.in` files are parsed in parallel.
+    pub fn all_package_configs(&self) -> Vec<(&String, Result<Vec<PackageConfig>, Error>)> {
+        let mut packages: Vec<&String> = self.packages().map(|(n, _)| n).collect();
+        packages.sort();
+        let configs = pool::map(&packages, |n| self.package_configs(n));
+        packages.into_iter().zip(configs).collect()
+    }
+
+    /// Return the patches of the package named `name`, sorted by file name.
+    ///
+    /// Like Buildroot, the patches are taken from the subdirectory named
+    /// after the version of the package, if any.
+    pub fn package_patches(&self, name: &str) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.package_files_dir(name)?;
+        let mut patches = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|e| e == "patch") {
+                patches.push(path);
+            }
+        }
+        patches.sort();
+        Ok(patches)
+    }
+
+    /// Return the entries of the hash file of the package named `name`.
+    ///
+    /// Like Buildroot, the hash file is taken from the subdirectory named
+    /// after the version of the package if it exists there, and from the
+    /// directory of the package otherwise.
+    pub fn package_hashes(&self, name: &str) -> Result<Vec<HashEntry>, Error> {
+        let dir = self.package_dir(name)?;
+        let file = format!("{name}.hash");
+        let path = [self.package_version_dir(name, &dir), Some(dir.clone())]
+            .into_iter()
+            .flatten()
+            .map(|d| d.join(&file))
+            .find(|p| p.exists());
+        match path {
+            Some(path) => Ok(HashEntry::from_path(path)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn package_files_dir(&self, name: &str) -> Result<PathBuf, Error> {
+        let dir = self.package_dir(name)?;
+        Ok(self.package_version_dir(name, &dir).unwrap_or(dir))
+    }
+
+    fn package_dir(&self, name: &str) -> Result<PathBuf, Error> {
+        let path = self.package_path(name)?;
+        Ok(path.parent().unwrap_or(Path::new("")).to_path_buf())
+    }
+
+    /// Return the subdirectory of `dir` named after the version of the
+    /// package named `name`, if it exists.
+    fn package_version_dir(&self, name: &str, dir: &Path) -> Option<PathBuf> {
+        self.package_info(name)
+            .ok()
+            .map(|i| dir.join(i.version()))
+            .filter(|d| d.is_dir())
+    }
+
     /// Return the version of a package named `name`
     pub fn get_package_version(&self, name: &str) -> Result<String, Error> {
-        let path = self
-            .packages()
-            .find(|(n, _)| n.as_str() == name)
-            .map(|(_, p)| p)
-            .ok_or_else(|| Error::UnknownPackage(name.to_string()))?;
-        let pkg = package::PackageInfo::from_path(path)?;
-        let version = pkg.version();
-        Ok(version.to_string())
+        let info = self.package_info(name)?;
+        Ok(info.version().to_string())
     }
 
     /// Set the version of the package named `name` to `version`
     pub fn set_package_version(&self, name: &str, version: &str) -> Result<(), Error> {
-        let path = self
-            .packages()
-            .find(|(n, _)| n.as_str() == name)
-            .map(|(_, p)| p)
-            .ok_or_else(|| Error::UnknownPackage(name.to_string()))?;
+        let path = self.package_path(name)?;
         package::set_package_version(path, version)?;
+        self.infos.lock().unwrap().remove(name);
         Ok(())
     }
 
+    fn package_path(&self, name: &str) -> Result<&PathBuf, Error> {
+        // The last tree wins, like in packages().
+        self.trees
+            .iter()
+            .rev()
+            .find_map(|t| t.base().packages.get(name))
+            .ok_or_else(|| Error::UnknownPackage(name.to_string()))
+    }
+
     /// Return information from a defconfig named `name`.
     pub fn get_defconfig(&self, name: &str) -> Result<Defconfig, Error> {
         self.defconfigs()
@@ -249,6 +676,160 @@ impl Buildroot {
             .and_then(|(_, p)| Ok(defconfig::Defconfig::from_path(p)?))
     }
 
+    /// Return the packages enabled by the defconfig named `name`, with their
+    /// dependencies, sorted by name.
+    ///
+    /// Only the packages enabled in the defconfig and the ones listed in the
+    /// `<PKG>_DEPENDENCIES` variables are returned, as the `select` statements
+    /// and default values of the configuration are not evaluated. Host
+    /// packages are prefixed with `host-`.
+    pub fn defconfig_packages(&self, name: &str) -> Result<Vec<String>, Error> {
+        let defconfig = self.get_defconfig(name)?;
+        Ok(self.dependency_graph(&defconfig).into_keys().collect())
+    }
+
+    /// Return licensing information about the packages enabled by the
+    /// defconfig named `name` and their dependencies.
+    ///
+    /// Like [`Buildroot::defconfig_packages()`], packages enabled by default
+    /// or selected by other packages are missed: use
+    /// [`Buildroot::config_licenses()`] on the configuration of an output
+    /// directory for a complete report.
+    pub fn licenses(&self, name: &str) -> Result<LicenseReport, Error> {
+        let defconfig = self.get_defconfig(name)?;
+        self.report_licenses(&defconfig)
+    }
+
+    /// Return licensing information about the packages enabled by the
+    /// resolved configuration at `path`, like the `.config` file of an output
+    /// directory, and their dependencies.
+    pub fn config_licenses<P: AsRef<Path>>(&self, path: P) -> Result<LicenseReport, Error> {
+        let config = Defconfig::from_path(path)?;
+        self.report_licenses(&config)
+    }
+
+    fn report_licenses(&self, config: &Defconfig) -> Result<LicenseReport, Error> {
+        let graph = self.dependency_graph(config);
+        let license_of = |name: &str| {
+            let name = name.strip_prefix("host-").unwrap_or(name);
+            self.package_info(name)
+                .ok()
+                .and_then(|i| i.property("license"))
+                .unwrap_or("unknown".to_string())
+        };
+        let mut report = LicenseReport::default();
+        for package in graph.keys() {
+            let base = package.strip_prefix("host-").unwrap_or(package);
+            let info = match self.package_info(base) {
+                Ok(info) => info,
+                Err(Error::Package(package::Error::MissingVariable(_))) => {
+                    report.skipped.push(package.to_string());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let version = info.version().to_string();
+            let property = |n| info.property(n).unwrap_or_default();
+            let license = license_of(package);
+            let source = info
+                .property("source")
+                .unwrap_or(format!("{base}-{version}.tar.gz"));
+            // Like Buildroot, only report host dependencies.
+            let dependencies = recursive_dependencies(&graph, package)
+                .into_iter()
+                .filter(|d| d.starts_with("host-") && d != package)
+                .map(|d| {
+                    let license = license_of(&d);
+                    (d, license)
+                })
+                .collect();
+            report.entries.push(LicenseEntry {
+                package: package.to_string(),
+                version,
+                spdx: legal::to_spdx(&license),
+                license,
+                license_files: property("license_files")
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+                source,
+                site: property("site"),
+                redistribute: property("redistribute") != "NO",
+                dependencies,
+            });
+        }
+        Ok(report)
+    }
+
+    fn dependency_graph(&self, defconfig: &Defconfig) -> BTreeMap<String, Vec<String>> {
+        let mut symbols = HashMap::new();
+        for (name, _) in self.packages() {
+            symbols.insert(package::package_symbol(name), name.to_string());
+            let host = format!("host-{name}");
+            symbols.insert(package::package_symbol(&host), host);
+        }
+        let mut pending: Vec<String> = defconfig
+            .symbols()
+            .iter()
+            .filter(|s| matches!(s.value, SymbolValue::Bool(true)))
+            .filter_map(|s| symbols.get(&s.name).cloned())
+            .collect();
+        let mut graph = BTreeMap::new();
+        while let Some(package) = pending.pop() {
+            if graph.contains_key(&package) {
+                continue;
+            }
+            let dependencies = self.package_dependencies(&package, &symbols);
+            pending.extend(dependencies.iter().cloned());
+            graph.insert(package, dependencies);
+        }
+        graph
+    }
+
+    fn package_dependencies(&self, name: &str, symbols: &HashMap<String, String>) -> Vec<String> {
+        let (host, base) = match name.strip_prefix("host-") {
+            Some(base) => (true, base),
+            None => (false, name),
+        };
+        let Ok(info) = self.package_info(base) else {
+            return vec![];
+        };
+        let dependencies = match host {
+            true => info.host_dependencies(),
+            false => info.dependencies(),
+        };
+        dependencies
+            .into_iter()
+            .filter(|d| symbols.contains_key(&package::package_symbol(d)))
+            .collect()
+    }
+
+    /// Return the files referenced by each defconfig, sorted by defconfig name.
+    pub fn boards(&self) -> Result<Vec<Board>, Error> {
+        let topdir = self.main_tree_path();
+        let variables: HashMap<String, PathBuf> = self
+            .externals()
+            .map(|e| (e.path_variable(), e.path().to_path_buf()))
+            .collect();
+        let mut defconfigs: Vec<(&String, &PathBuf)> = self.defconfigs().collect();
+        defconfigs.sort();
+        defconfigs
+            .into_iter()
+            .map(|(n, p)| {
+                let defconfig = Defconfig::from_path(p)?;
+                Ok(Board::from_defconfig(n, &defconfig, topdir, &variables))
+            })
+            .collect()
+    }
+
+    /// Return the files in the board, configs and patches directories of the
+    /// external trees which are not referenced by any defconfig.
+    pub fn orphans(&self) -> Result<Vec<PathBuf>, Error> {
+        let boards = self.boards()?;
+        let roots: Vec<&Path> = self.externals().map(|e| e.path()).collect();
+        Ok(board::find_orphans(&roots, &boards)?)
+    }
+
     /// Create a builder for a given defconfig
     pub fn create_builder<P: AsRef<Path>>(&self, name: &str, output: P) -> Result<Builder, Error> {
         let defconfig = self
@@ -262,18 +843,25 @@ impl Buildroot {
             .iter()
             .skip(1)
             .filter_map(|t| {
-                if let BuildrootTree::External(_, t) = t {
-                    Some(t.path.to_path_buf())
+                if let BuildrootTree::External(e, _) = t {
+                    Some(e.path().to_path_buf())
                 } else {
                     None
                 }
             })
             .collect();
+        let version = self.version().ok().map(|v| v.to_string());
         Ok(Builder {
             defconfig,
             output: output.as_ref().to_path_buf(),
             main,
             externals,
+            version,
+            fragments: vec![],
+            options: Default::default(),
+            symbols: BTreeMap::new(),
+            logs: None,
+            no_log: false,
         })
     }
 
@@ -290,7 +878,7 @@ impl Buildroot {
     }
 
     /// Return the path to the main tree
-    fn main_tree_path(&self) -> &Path {
+    pub fn main_tree_path(&self) -> &Path {
         if let BuildrootTree::Main(m) = &self.trees[0] {
             m.path.as_path()
         } else {
@@ -299,7 +887,55 @@ impl Buildroot {
     }
 }
 
-#[derive(Debug)]
+fn recursive_dependencies(graph: &BTreeMap<String, Vec<String>>, name: &str) -> Vec<String> {
+    let mut found: Vec<String> = vec![];
+    let mut pending = vec![name];
+    while let Some(package) = pending.pop() {
+        for dependency in graph.get(package).into_iter().flatten() {
+            if !found.contains(dependency) {
+                found.push(dependency.clone());
+                pending.push(dependency);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Extract the path to the main tree from the `Makefile` generated by
+/// Buildroot in an output directory, i.e. from `MAKEARGS := -C <path>`.
+fn parse_output_makefile(contents: &str) -> Option<PathBuf> {
+    contents.lines().find_map(|l| {
+        let (var, value) = l.split_once(":=")?;
+        if var.trim() != "MAKEARGS" {
+            return None;
+        }
+        value
+            .trim()
+            .strip_prefix("-C")
+            .map(|p| PathBuf::from(p.trim()))
+    })
+}
+
+/// Extract the paths to the external trees from a `.br2-external.mk` file.
+fn parse_br2_external_mk(contents: &str) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .filter_map(|l| {
+            let (var, value) = l.split_once("+=")?;
+            (var.trim() == "BR2_EXTERNAL_DIRS").then(|| PathBuf::from(value.trim()))
+        })
+        .collect()
+}
+
+/// Split the value of `BR2_EXTERNAL`, where paths are separated by colons or spaces.
+fn split_br2_external(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+}
+
+#[derive(Debug, PartialEq)]
 enum BuildrootTreePath {
     Main(PathBuf),
     External(PathBuf),
@@ -309,6 +945,7 @@ enum BuildrootTreePath {
 #[derive(Debug)]
 pub struct BuildrootExplorer {
     paths: Vec<BuildrootTreePath>,
+    cache: Option<Cache>,
 }
 
 impl BuildrootExplorer {
@@ -316,26 +953,82 @@ impl BuildrootExplorer {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         let path = BuildrootTreePath::Main(path.as_ref().to_path_buf());
         let paths = vec![path];
-        Self { paths }
+        Self { paths, cache: None }
+    }
+
+    /// Construct a new `BuildrootExplorer` from the output directory of a
+    /// configured build, using the main tree and the external trees it was
+    /// configured with.
+    pub fn from_output_dir<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let makefile = fs::read_to_string(path.join("Makefile"))
+            .map_err(|_| Error::InvalidOutputDirectory(path.to_path_buf()))?;
+        let main = parse_output_makefile(&makefile)
+            .ok_or_else(|| Error::InvalidOutputDirectory(path.to_path_buf()))?;
+        let mut explorer = Self::new(main);
+        let ext_path = path.join(".br2-external.mk");
+        if ext_path.exists() {
+            let contents = fs::read_to_string(ext_path)?;
+            for external in parse_br2_external_mk(&contents) {
+                explorer.external_tree(external);
+            }
+        }
+        Ok(explorer)
+    }
+
+    /// Construct a new `BuildrootExplorer` using the current directory as
+    /// the main Buildroot directory, and the external trees listed in the
+    /// `BR2_EXTERNAL` environment variable.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut explorer = Self::new(std::env::current_dir()?);
+        if let Some(value) = std::env::var_os("BR2_EXTERNAL") {
+            for external in split_br2_external(&value.to_string_lossy()) {
+                explorer.external_tree(external);
+            }
+        }
+        Ok(explorer)
     }
 
     /// Add `path` as an external source tree to be explored.
+    ///
+    /// A path which has already been added is ignored.
     pub fn external_tree<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
         let path = BuildrootTreePath::External(path.as_ref().to_path_buf());
-        self.paths.push(path);
+        if !self.paths.contains(&path) {
+            self.paths.push(path);
+        }
+        self
+    }
+
+    /// Use `cache` to store the index of the source trees between explorations.
+    pub fn cache(&mut self, cache: Cache) -> &mut Self {
+        self.cache = Some(cache);
         self
     }
 
     /// Explore all the source trees and consume the `BuildrootExplorer`, providing a `Buildroot` in return.
     pub fn explore(self) -> Result<Buildroot, Error> {
-        let trees: Result<Vec<BuildrootTree>, Error> =
-            self.paths.iter().map(BuildrootTree::from_path).collect();
-        Ok(Buildroot { trees: trees? })
+        let trees: Result<Vec<BuildrootTree>, Error> = self
+            .paths
+            .iter()
+            .map(|p| BuildrootTree::from_path(p, self.cache.as_ref()))
+            .collect();
+        let trees = trees?;
+        let mut names = HashSet::new();
+        for tree in trees.iter().skip(1) {
+            if !names.insert(tree.name()) {
+                return Err(Error::DuplicateExternalTree(tree.name().to_string()));
+            }
+        }
+        Ok(Buildroot {
+            trees,
+            infos: Mutex::new(HashMap::new()),
+        })
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::fs;
     use std::path::Path;
@@ -346,13 +1039,14 @@ mod tests {
 
 @NAME@_SITE = http://some/where
 
+$(eval $(generic-package))
 "##;
     const TEMPLATE_CONFIG: &str = r##"
 BR2_PACKAGE_FOO=y
 # BR2_PACKAGE_BAR is not set
 "##;
 
-    const BUILDROOT_TEST_DIR: &str = "br2-utils-test";
+    pub(crate) const BUILDROOT_TEST_DIR: &str = "br2-utils-test";
 
     fn mock_config<P: AsRef<Path>>(dir: P, name: &str) -> std::io::Result<()> {
         let path = dir.as_ref().join(format!("{}_defconfig", name));
@@ -379,10 +1073,12 @@ BR2_PACKAGE_FOO=y
         for name in ["foo", "bar"] {
             mock_package(&dir, name)?;
         }
+        fs::write(dir.as_ref().join("pkg-generic.mk"), "# Helpers\n")?;
+        fs::write(dir.as_ref().join("foo").join("foo-extra.mk"), "# Extra\n")?;
         Ok(())
     }
 
-    fn mock_tree<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    pub(crate) fn mock_tree<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
         for dir in BUILDROOT_SUBDIRS {
             let path = path.as_ref().join(dir);
             fs::create_dir(&path)?;
@@ -395,6 +1091,17 @@ BR2_PACKAGE_FOO=y
         Ok(())
     }
 
+    pub(crate) fn mock_external<P: AsRef<Path>>(path: P, name: &str) -> std::io::Result<()> {
+        let desc = format!("name: {}\ndesc: External tree {}\n", name, name);
+        fs::write(path.as_ref().join("external.desc"), desc)?;
+        let path = path.as_ref().join("package");
+        fs::create_dir(&path)?;
+        mock_package(&path, "foo")?;
+        let path = path.join("foo").join("foo.mk");
+        let contents = fs::read_to_string(&path)?.replace("1.2.3", "4.5.6");
+        fs::write(path, contents)
+    }
+
     #[test]
     fn check_valid_buildroot() {
         let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
@@ -409,6 +1116,172 @@ BR2_PACKAGE_FOO=y
         assert_eq!(packages, ["bar", "foo"]);
     }
 
+    #[test]
+    fn check_package_collision() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        mock_package(path.path().join("package").join("bar"), "foo").unwrap();
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let expected = path.path().join("package").join("foo").join("foo.mk");
+        assert_eq!(buildroot.package_path("foo").unwrap(), &expected);
+        let origins = buildroot.package_origin("foo");
+        let paths: Vec<(&Path, bool)> = origins.iter().map(|o| (o.path, o.shadowed)).collect();
+        let other = path
+            .path()
+            .join("package")
+            .join("bar")
+            .join("foo")
+            .join("foo.mk");
+        assert_eq!(
+            paths,
+            [(other.as_path(), true), (expected.as_path(), false)]
+        );
+        assert_eq!(buildroot.duplicate_packages().len(), 1);
+    }
+
+    #[test]
+    fn check_trees() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let ext_path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_external(&ext_path, "ACME").unwrap();
+        fs::create_dir(ext_path.path().join("configs")).unwrap();
+        mock_config(ext_path.path().join("configs"), "acme_foo").unwrap();
+        let mut explorer = BuildrootExplorer::new(&path);
+        explorer.external_tree(&ext_path);
+        let buildroot = explorer.explore().unwrap();
+        let trees: Vec<(TreeKind, &str)> =
+            buildroot.trees().map(|t| (t.kind(), t.name())).collect();
+        assert_eq!(
+            trees,
+            [
+                (TreeKind::Main, MAIN_TREE_NAME),
+                (TreeKind::External, "ACME")
+            ]
+        );
+        let tree = buildroot.defconfig_tree("acme_foo_defconfig").unwrap();
+        assert_eq!(tree.name(), "ACME");
+        assert_eq!(tree.path(), ext_path.path());
+        let tree = buildroot.tree(MAIN_TREE_NAME).unwrap();
+        assert!(tree.external().is_none());
+        assert!(tree.has_package("foo") && tree.has_package("bar"));
+        assert_eq!(tree.defconfigs().count(), 2);
+    }
+
+    #[test]
+    fn check_package_shadowed() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let ext_path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_external(&ext_path, "ACME").unwrap();
+        let mut explorer = BuildrootExplorer::new(&path);
+        explorer.external_tree(&ext_path);
+        let buildroot = explorer.explore().unwrap();
+        assert_eq!(buildroot.packages().filter(|(n, _)| *n == "foo").count(), 1);
+        assert_eq!(buildroot.get_package_version("foo").unwrap(), "4.5.6");
+        let origins = buildroot.package_origin("foo");
+        let trees: Vec<(&str, bool)> = origins.iter().map(|o| (o.tree, o.shadowed)).collect();
+        assert_eq!(trees, [(MAIN_TREE_NAME, true), ("ACME", false)]);
+        let duplicates = buildroot.duplicate_packages();
+        assert_eq!(duplicates.keys().collect::<Vec<_>>(), [&"foo"]);
+    }
+
+    #[test]
+    fn parse_external_desc() {
+        let contents = "\n# Comment\nname: ACME_2\ndesc: Acme: board support\n";
+        let (name, desc) = ExternalTree::parse_desc(contents).unwrap();
+        assert_eq!(name, "ACME_2");
+        assert_eq!(desc, "Acme: board support");
+        assert!(ExternalTree::parse_desc("desc: No name\n").is_none());
+        assert!(ExternalTree::parse_desc("name ACME\n").is_none());
+        assert!(ExternalTree::parse_desc("name: main\n").is_none());
+    }
+
+    #[test]
+    fn check_external_tree() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_external(&path, "ACME").unwrap();
+        let external = ExternalTree::from_path(&path).unwrap();
+        assert_eq!(external.name(), "ACME");
+        assert_eq!(external.description(), "External tree ACME");
+        assert_eq!(external.path_variable(), "BR2_EXTERNAL_ACME_PATH");
+        fs::write(path.path().join("external.desc"), "name: ACME-2\n").unwrap();
+        let res = ExternalTree::from_path(&path);
+        assert!(matches!(res, Err(Error::InvalidExternalTreeName(_))));
+    }
+
+    #[test]
+    fn explore_from_output_dir() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let ext_path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_external(&ext_path, "ACME").unwrap();
+        let output = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        let makefile = format!(
+            "# Automatically generated\nMAKEARGS := -C {}\nMAKEARGS += O=$(CURDIR)\n",
+            path.path().display()
+        );
+        fs::write(output.path().join("Makefile"), makefile).unwrap();
+        let ext_mk = format!(
+            "BR2_EXTERNAL ?= {0}\nBR2_EXTERNAL_DIRS = \n\nBR2_EXTERNAL_NAMES += ACME\nBR2_EXTERNAL_DIRS += {0}\n",
+            ext_path.path().display()
+        );
+        fs::write(output.path().join(".br2-external.mk"), ext_mk).unwrap();
+        let buildroot = BuildrootExplorer::from_output_dir(&output)
+            .unwrap()
+            .explore()
+            .unwrap();
+        let names: Vec<&str> = buildroot.externals().map(|e| e.name()).collect();
+        assert_eq!(names, ["ACME"]);
+        assert_eq!(buildroot.get_package_version("foo").unwrap(), "4.5.6");
+    }
+
+    #[test]
+    fn split_br2_external_value() {
+        let paths: Vec<&str> = split_br2_external("/a/b:/c  /d:").collect();
+        assert_eq!(paths, ["/a/b", "/c", "/d"]);
+    }
+
+    #[test]
+    fn explore_with_cache() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let cache_dir = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        let cache = Cache::new(&cache_dir);
+        let mut explorer = BuildrootExplorer::new(&path);
+        explorer.cache(cache.clone());
+        let buildroot = explorer.explore().unwrap();
+        assert_eq!(buildroot.packages().count(), 2);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        // Unmodified directories are not scanned again.
+        let pkg_dir = path.path().join("package");
+        let mut buildroot = None;
+        for name in ["quux", "wuz"] {
+            mock_package(&pkg_dir, name).unwrap();
+            let mut explorer = BuildrootExplorer::new(&path);
+            explorer.cache(cache.clone());
+            buildroot = Some(explorer.explore().unwrap());
+        }
+        let buildroot = buildroot.unwrap();
+        let mut packages: Vec<&str> = buildroot.packages().map(|(n, _)| n.as_str()).collect();
+        packages.sort();
+        assert_eq!(packages, ["bar", "foo", "quux", "wuz"]);
+
+        // Makefiles edited in place are checked again.
+        let foo_dir = pkg_dir.join("foo");
+        let mtime = fs::metadata(&foo_dir).unwrap().modified().unwrap();
+        fs::write(foo_dir.join("foo.mk"), "# Not a package anymore\n").unwrap();
+        assert_eq!(fs::metadata(&foo_dir).unwrap().modified().unwrap(), mtime);
+        let mut explorer = BuildrootExplorer::new(&path);
+        explorer.cache(cache.clone());
+        let buildroot = explorer.explore().unwrap();
+        assert!(buildroot.packages().all(|(n, _)| n != "foo"));
+
+        cache.clear().unwrap();
+        assert!(!cache_dir.path().exists());
+    }
+
     #[test]
     fn get_package_version() {
         let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
@@ -426,6 +1299,131 @@ BR2_PACKAGE_FOO=y
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn get_package_configs() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let dir = path.path().join("package").join("foo");
+        let config = "config BR2_PACKAGE_FOO\n\tbool \"foo\"\n\nconfig BR2_PACKAGE_FOO_EXTRA\n\tbool \"extra\"\n";
+        fs::write(dir.join("Config.in"), config).unwrap();
+        let config = "config BR2_PACKAGE_HOST_FOO\n\tbool \"host foo\"\n";
+        fs::write(dir.join("Config.in.host"), config).unwrap();
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let configs = buildroot.package_configs("foo").unwrap();
+        let symbols: Vec<&str> = configs.iter().map(|c| c.symbol.as_str()).collect();
+        assert_eq!(symbols, ["BR2_PACKAGE_FOO", "BR2_PACKAGE_HOST_FOO"]);
+        assert!(buildroot.package_configs("bar").unwrap().is_empty());
+        let all = buildroot.all_package_configs();
+        let names: Vec<&str> = all.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["bar", "foo"]);
+        assert_eq!(all[1].1.as_ref().unwrap(), &configs);
+    }
+
+    #[test]
+    fn get_package_patches_and_hashes() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let dir = path.path().join("package").join("foo");
+        fs::write(dir.join("0002-fix-build.patch"), "").unwrap();
+        fs::write(dir.join("0001-fix-install.patch"), "").unwrap();
+        fs::write(dir.join("foo.hash"), "sha256  0123abcd  foo-1.2.3.tar.gz\n").unwrap();
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let patches = buildroot.package_patches("foo").unwrap();
+        let names: Vec<_> = patches.iter().filter_map(|p| p.file_name()).collect();
+        assert_eq!(names, ["0001-fix-install.patch", "0002-fix-build.patch"]);
+        assert_eq!(buildroot.package_hashes("foo").unwrap().len(), 1);
+        assert!(buildroot.package_hashes("bar").unwrap().is_empty());
+
+        // Patches of the subdirectory named after the version replace the
+        // others, while the hash file falls back to the package directory.
+        let version_dir = dir.join("1.2.3");
+        fs::create_dir(&version_dir).unwrap();
+        fs::write(version_dir.join("0001-backport.patch"), "").unwrap();
+        let patches = buildroot.package_patches("foo").unwrap();
+        assert_eq!(patches, [version_dir.join("0001-backport.patch")]);
+        let hashes = buildroot.package_hashes("foo").unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[0].hash, "0123abcd");
+
+        fs::write(
+            version_dir.join("foo.hash"),
+            "sha256  4567ef  foo-1.2.3.tar.gz\n",
+        )
+        .unwrap();
+        let hashes = buildroot.package_hashes("foo").unwrap();
+        assert_eq!(hashes[0].hash, "4567ef");
+    }
+
+    #[test]
+    fn get_defconfig_licenses() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let dir = path.path().join("package");
+        mock_package(&dir, "baz").unwrap();
+        fs::create_dir(dir.join("virt")).unwrap();
+        fs::write(
+            dir.join("virt").join("virt.mk"),
+            "$(eval $(virtual-package))\n",
+        )
+        .unwrap();
+        let extra = "FOO_LICENSE = GPL-2.0+\nFOO_LICENSE_FILES = COPYING\nFOO_DEPENDENCIES = bar host-baz virt $(if $(BR2_X),qux)\n";
+        let mk = dir.join("foo").join("foo.mk");
+        let contents = fs::read_to_string(&mk).unwrap();
+        fs::write(&mk, format!("{contents}{extra}")).unwrap();
+        let extra = "BAR_LICENSE = MIT\nBAR_REDISTRIBUTE = NO\nBAR_DEPENDENCIES = baz\n";
+        let mk = dir.join("bar").join("bar.mk");
+        let contents = fs::read_to_string(&mk).unwrap();
+        fs::write(&mk, format!("{contents}{extra}")).unwrap();
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let packages = buildroot.defconfig_packages("acme_quux_defconfig").unwrap();
+        assert_eq!(packages, ["bar", "baz", "foo", "host-baz", "virt"]);
+        let report = buildroot.licenses("acme_quux_defconfig").unwrap();
+        assert_eq!(report.skipped, ["virt"]);
+        let entries = report.entries;
+        let foo = entries.iter().find(|e| e.package == "foo").unwrap();
+        assert_eq!(foo.spdx, "GPL-2.0-or-later");
+        assert_eq!(foo.license_files, ["COPYING"]);
+        assert_eq!(foo.source, "foo-1.2.3.tar.gz");
+        assert_eq!(
+            foo.dependencies.iter().collect::<Vec<_>>(),
+            [(&"host-baz".to_string(), &"unknown".to_string())]
+        );
+        let bar = entries.iter().find(|e| e.package == "bar").unwrap();
+        assert!(!bar.redistribute);
+        assert!(entries.iter().any(|e| e.is_host()));
+
+        // The resolved configuration also holds the packages enabled by
+        // default or selected by other packages.
+        let config = path.path().join(".config");
+        fs::write(
+            &config,
+            "#\n# Target packages\n#\nBR2_PACKAGE_BAZ=y\n# BR2_PACKAGE_FOO is not set\n",
+        )
+        .unwrap();
+        let report = buildroot.config_licenses(&config).unwrap();
+        let packages: Vec<&str> = report.entries.iter().map(|e| e.package.as_str()).collect();
+        assert_eq!(packages, ["baz"]);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn get_package_infos() {
+        let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
+        mock_tree(&path).unwrap();
+        let buildroot = BuildrootExplorer::new(&path).explore().unwrap();
+        let infos = buildroot.package_infos();
+        let names: Vec<&str> = infos.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["bar", "foo"]);
+        assert!(infos.iter().all(|(_, i)| i.is_ok()));
+        let info = buildroot.package_info("foo").unwrap();
+        assert!(Arc::ptr_eq(&info, infos[1].1.as_ref().unwrap()));
+
+        // Changing the version discards the memoised information.
+        assert_eq!(buildroot.get_package_version("foo").unwrap(), "1.2.3");
+        buildroot.set_package_version("foo", "3.2.1").unwrap();
+        assert_eq!(buildroot.get_package_version("foo").unwrap(), "3.2.1");
+    }
+
     #[test]
     fn check_package_not_selected() {
         let path = Builder::new().prefix(BUILDROOT_TEST_DIR).tempdir().unwrap();
@@ -0,0 +1,172 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide an on-disk cache for the index of Buildroot trees.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use thiserror::Error;
+
+/// Version of the format of the cached indexes.
+const CACHE_VERSION: u32 = 2;
+
+/// Errors reported when handling the cache.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("TOML deserialization error: {0}")]
+    TomlDeserialize(#[from] toml::de::Error),
+    #[error("TOML serialization error: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+}
+
+/// Modification time of a directory.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct ModificationTime {
+    secs: u64,
+    nanos: u32,
+}
+
+impl From<SystemTime> for ModificationTime {
+    fn from(time: SystemTime) -> Self {
+        let duration = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            secs: duration.as_secs(),
+            nanos: duration.subsec_nanos(),
+        }
+    }
+}
+
+/// Modification time and size of a file.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct FileStamp {
+    mtime: ModificationTime,
+    size: u64,
+}
+
+impl FileStamp {
+    /// Create the stamp of a file from its metadata.
+    pub(crate) fn from_metadata(metadata: &fs::Metadata) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            mtime: metadata.modified()?.into(),
+            size: metadata.len(),
+        })
+    }
+}
+
+/// Items found directly in a directory of a tree.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct DirectoryIndex {
+    pub(crate) mtime: ModificationTime,
+    pub(crate) items: Vec<String>,
+    /// Stamps of the files whose contents tell whether they are items.
+    pub(crate) files: BTreeMap<String, FileStamp>,
+}
+
+/// Index of a tree, holding the items of each of its directories.
+///
+/// As adding, removing or renaming a file changes the modification time of
+/// its directory, only the directories whose modification time changed need
+/// to be scanned again. Editing a file in place does not, so the files whose
+/// contents matter, like package makefiles, are also checked individually.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct TreeIndex {
+    version: u32,
+    path: PathBuf,
+    pub(crate) directories: BTreeMap<String, DirectoryIndex>,
+}
+
+impl TreeIndex {
+    /// Create an empty index for the tree at `path`.
+    pub(crate) fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            path: path.as_ref().to_path_buf(),
+            directories: BTreeMap::new(),
+        }
+    }
+
+    /// Return the index of the directory at `key`, if still up to date.
+    pub(crate) fn get(
+        &self,
+        key: &str,
+        mtime: ModificationTime,
+        files: &BTreeMap<String, FileStamp>,
+    ) -> Option<&DirectoryIndex> {
+        self.directories
+            .get(key)
+            .filter(|d| d.mtime == mtime && d.files == *files)
+    }
+}
+
+/// On-disk cache of tree indexes.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Create a new cache, using `dir` as location for the indexes.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Remove all the cached indexes.
+    pub fn clear(&self) -> Result<(), Error> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Load the index of the tree at `path`, if any.
+    pub(crate) fn load<P: AsRef<Path>>(&self, path: P) -> Result<Option<TreeIndex>, Error> {
+        let index_path = self.index_path(&path);
+        if !index_path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(index_path)?;
+        let index: TreeIndex = toml::from_str(&text)?;
+        let valid = index.version == CACHE_VERSION && index.path == path.as_ref();
+        Ok(valid.then_some(index))
+    }
+
+    /// Store the index of a tree.
+    pub(crate) fn store(&self, index: &TreeIndex) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let text = toml::to_string(index)?;
+        // Write to a temporary file first, so that concurrent readers never
+        // see a partial index.
+        let tmp_path = self.index_path(&index.path).with_extension("tmp");
+        fs::write(&tmp_path, text)?;
+        fs::rename(tmp_path, self.index_path(&index.path))?;
+        Ok(())
+    }
+
+    fn index_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        // FNV-1a, as the hash must be stable across runs.
+        let hash = path
+            .as_ref()
+            .to_string_lossy()
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |h, b| {
+                (h ^ b as u64).wrapping_mul(0x100000001b3)
+            });
+        self.dir.join(format!("{:016x}.toml", hash))
+    }
+}
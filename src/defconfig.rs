@@ -10,11 +10,12 @@
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 use thiserror::Error;
 
 use std::{
     fs::File,
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, Read, Write},
     path::Path,
     str::FromStr,
 };
@@ -31,9 +32,12 @@ pub enum Error {
 }
 
 /// Value of a symbol in a `Defconfig`.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
 pub enum SymbolValue {
     Bool(bool),
+    Int(i64),
+    Hex(u64),
     String(String),
 }
 
@@ -41,18 +45,24 @@ impl FromStr for SymbolValue {
     type Err = self::Error;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        if s.starts_with('\"') && s.ends_with('\"') {
-            let s = s
-                .trim_start_matches('\"')
-                .trim_end_matches('\"')
-                .to_string();
-            return Ok(SymbolValue::String(s));
+        if s.len() >= 2 && s.starts_with('\"') && s.ends_with('\"') {
+            // Quotes inside the value are escaped, so keep them.
+            return Ok(SymbolValue::String(s[1..s.len() - 1].to_string()));
+        }
+
+        if let Some(h) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            return u64::from_str_radix(h, 16)
+                .map(SymbolValue::Hex)
+                .map_err(|_| Error::InvalidValue(s.to_string()));
         }
 
         match s {
             "y" => Ok(SymbolValue::Bool(true)),
             "n" => Ok(SymbolValue::Bool(false)),
-            _ => Err(Error::InvalidValue(s.to_string())),
+            _ => s
+                .parse::<i64>()
+                .map(SymbolValue::Int)
+                .map_err(|_| Error::InvalidValue(s.to_string())),
         }
     }
 }
@@ -64,13 +74,15 @@ impl std::fmt::Display for SymbolValue {
                 let v = if *v { "y" } else { "n" };
                 write!(f, "{}", v)
             }
+            SymbolValue::Int(v) => write!(f, "{}", v),
+            SymbolValue::Hex(v) => write!(f, "0x{:x}", v),
             SymbolValue::String(v) => write!(f, "{}", v),
         }
     }
 }
 
 /// Represent a symbol in a `Defconfig`.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Symbol {
     pub name: String,
     pub value: SymbolValue,
@@ -104,8 +116,18 @@ impl FromStr for Symbol {
     }
 }
 
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            SymbolValue::Bool(false) => write!(f, "# {} is not set", self.name),
+            SymbolValue::String(ref v) => write!(f, "{}=\"{}\"", self.name, v),
+            ref v => write!(f, "{}={}", self.name, v),
+        }
+    }
+}
+
 /// Hold information of a defconfig.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct Defconfig {
     symbols: Vec<Symbol>,
 }
@@ -120,7 +142,7 @@ impl Defconfig {
     /// Construct a `Defconfig` from a readable object.
     pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
         let reader = BufReader::new(reader);
-        let mut symbols = vec![];
+        let mut defconfig = Self { symbols: vec![] };
         for line in reader.lines() {
             let line = line?;
             if line.is_empty() {
@@ -129,10 +151,10 @@ impl Defconfig {
             if line.starts_with('#') && !line.ends_with("is not set") {
                 continue;
             }
-            let symbol = line.parse::<Symbol>()?;
-            symbols.push(symbol);
+            // Like Kconfig, the last value of a symbol wins.
+            defconfig.set(line.parse::<Symbol>()?);
         }
-        Ok(Self { symbols })
+        Ok(defconfig)
     }
 
     /// Return the list of symbols.
@@ -140,6 +162,30 @@ impl Defconfig {
         &self.symbols
     }
 
+    /// Set the value of a symbol, keeping its position if already set.
+    pub fn set(&mut self, symbol: Symbol) {
+        match self.symbols.iter_mut().find(|s| s.name == symbol.name) {
+            Some(s) => s.value = symbol.value,
+            None => self.symbols.push(symbol),
+        }
+    }
+
+    /// Merge the symbols of `other`, which take precedence, like a config
+    /// fragment.
+    pub fn merge(&mut self, other: &Defconfig) {
+        for symbol in &other.symbols {
+            self.set(symbol.clone());
+        }
+    }
+
+    /// Write the symbols in the defconfig format.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        for symbol in &self.symbols {
+            writeln!(writer, "{symbol}")?;
+        }
+        Ok(())
+    }
+
     /// Check if a package is selected.
     pub fn selects(&self, package: &str) -> bool {
         let name = format!("BR2_PACKAGE_{}", package)
@@ -162,6 +208,8 @@ BR2_i386=y
 BR2_PACKAGE_FOO=y
 BR2_PACKAGE_FOO_BAR="1.2.3"
 # BR2_PACKAGE_QUUX is not set
+BR2_TARGET_ROOTFS_UBIFS_LEBSIZE=0x1f800
+BR2_TARGET_ROOTFS_UBIFS_MAXLEBCNT=2048
 "#;
 
     fn reference_defconfig() -> Defconfig {
@@ -183,10 +231,30 @@ BR2_PACKAGE_FOO_BAR="1.2.3"
                     name: "BR2_PACKAGE_QUUX".to_string(),
                     value: SymbolValue::Bool(false),
                 },
+                Symbol {
+                    name: "BR2_TARGET_ROOTFS_UBIFS_LEBSIZE".to_string(),
+                    value: SymbolValue::Hex(0x1f800),
+                },
+                Symbol {
+                    name: "BR2_TARGET_ROOTFS_UBIFS_MAXLEBCNT".to_string(),
+                    value: SymbolValue::Int(2048),
+                },
             ],
         }
     }
 
+    #[test]
+    fn symbol_quoted_string() {
+        let line = r#"BR2_TARGET_GENERIC_ISSUE="Welcome to \"foo\"""#;
+        let symbol = line.parse::<Symbol>().unwrap();
+        assert_eq!(
+            symbol.value,
+            SymbolValue::String(r#"Welcome to \"foo\""#.to_string())
+        );
+        assert_eq!(symbol.to_string(), line);
+        assert!("BR2_FOO=\"".parse::<Symbol>().is_err());
+    }
+
     #[test]
     fn symbol_no_value() {
         let res = "BR2_PACKAGE_FOO".parse::<Symbol>();
@@ -206,6 +274,31 @@ BR2_PACKAGE_FOO_BAR="1.2.3"
         assert_eq!(res.unwrap(), reference_defconfig());
     }
 
+    #[test]
+    fn merge_defconfig() {
+        let mut defconfig = Defconfig::from_reader(DEFCONFIG_VALID.as_bytes()).unwrap();
+        let fragment = "BR2_PACKAGE_FOO=n\nBR2_ENABLE_DEBUG=y\n";
+        let fragment = Defconfig::from_reader(fragment.as_bytes()).unwrap();
+        defconfig.merge(&fragment);
+        defconfig.set("BR2_PACKAGE_FOO_BAR=\"4.5.6\"".parse().unwrap());
+        let mut output = vec![];
+        defconfig.write(&mut output).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert_eq!(
+            text,
+            r#"BR2_i386=y
+# BR2_PACKAGE_FOO is not set
+BR2_PACKAGE_FOO_BAR="4.5.6"
+# BR2_PACKAGE_QUUX is not set
+BR2_TARGET_ROOTFS_UBIFS_LEBSIZE=0x1f800
+BR2_TARGET_ROOTFS_UBIFS_MAXLEBCNT=2048
+BR2_ENABLE_DEBUG=y
+"#
+        );
+        let reloaded = Defconfig::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(reloaded, defconfig);
+    }
+
     #[test]
     fn does_select() {
         let defconfig = Defconfig::from_reader(DEFCONFIG_VALID.as_bytes()).unwrap();
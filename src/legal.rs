@@ -0,0 +1,316 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for reviewing the licenses of packages, without building.
+
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// Columns of the `manifest.csv` file generated by `make legal-info`.
+const MANIFEST_COLUMNS: [&str; 7] = [
+    "PACKAGE",
+    "VERSION",
+    "LICENSE",
+    "LICENSE FILES",
+    "SOURCE ARCHIVE",
+    "SOURCE SITE",
+    "DEPENDENCIES WITH LICENSES",
+];
+
+/// Prefix of the identifiers of licenses not in the SPDX license list.
+const LICENSE_REF: &str = "LicenseRef-";
+
+/// Value used by Buildroot for the sources of packages not redistributed.
+const NOT_SAVED: &str = "not saved";
+
+/// Licensing information about a package.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LicenseEntry {
+    /// Name of the package, like `foo` or `host-foo`.
+    pub package: String,
+    /// Version of the package.
+    pub version: String,
+    /// License of the package, as declared by Buildroot.
+    pub license: String,
+    /// License of the package, as a SPDX expression.
+    pub spdx: String,
+    /// Files holding the license texts.
+    pub license_files: Vec<String>,
+    /// Name of the source archive.
+    pub source: String,
+    /// Location of the source archive.
+    pub site: String,
+    /// Whether the sources of the package may be redistributed.
+    pub redistribute: bool,
+    /// Host packages the package recursively depends on, with their licenses.
+    pub dependencies: BTreeMap<String, String>,
+}
+
+/// Licensing information about the packages enabled by a configuration.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LicenseReport {
+    /// Licensing information, sorted by package name.
+    pub entries: Vec<LicenseEntry>,
+    /// Packages without a version, like virtual packages, which are not
+    /// reported.
+    pub skipped: Vec<String>,
+}
+
+impl LicenseEntry {
+    /// Check if the entry is about a host package.
+    pub fn is_host(&self) -> bool {
+        self.package.starts_with("host-")
+    }
+}
+
+/// Write `entries` like `make legal-info` writes `manifest.csv`.
+pub fn write_manifest<W: Write>(entries: &[LicenseEntry], writer: W) -> Result<(), csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .quote_style(csv::QuoteStyle::Always)
+        .from_writer(writer);
+    writer.write_record(MANIFEST_COLUMNS)?;
+    for entry in entries {
+        let (source, site) = if entry.redistribute {
+            (entry.source.as_str(), entry.site.as_str())
+        } else {
+            (NOT_SAVED, NOT_SAVED)
+        };
+        let dependencies = entry
+            .dependencies
+            .iter()
+            .map(|(n, l)| format!("{n} [{l}]"))
+            .collect::<Vec<String>>()
+            .join(" ");
+        writer.write_record([
+            entry.package.as_str(),
+            entry.version.as_str(),
+            entry.license.as_str(),
+            entry.license_files.join(" ").as_str(),
+            source,
+            site,
+            dependencies.as_str(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Convert a license declared in a Buildroot package into a SPDX expression.
+///
+/// Buildroot separates licenses applying to different parts of a package
+/// with commas, alternatives with `or`, and may add comments between
+/// parentheses, like in `GPL-2.0+ (programs), LGPL-2.1+ or MIT (library)`.
+/// Exceptions, like in `GPL-3.0+ with GCC-exception-3.1`, are kept with the
+/// `WITH` operator. Identifiers which are not valid SPDX identifiers are
+/// turned into `LicenseRef-` references.
+pub fn to_spdx(license: &str) -> String {
+    if license.trim().eq_ignore_ascii_case("unknown") {
+        return "NOASSERTION".to_string();
+    }
+    let mut terms: Vec<String> = vec![];
+    for term in strip_comments(license).split(',') {
+        let choices: Vec<String> = term
+            .split(" or ")
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(spdx_identifier)
+            .collect();
+        let term = match choices.len() {
+            0 => continue,
+            1 => choices[0].clone(),
+            _ => format!("({})", choices.join(" OR ")),
+        };
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    match terms.len() {
+        0 => "NOASSERTION".to_string(),
+        1 => terms[0]
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .to_string(),
+        _ => terms.join(" AND "),
+    }
+}
+
+fn strip_comments(license: &str) -> String {
+    let mut depth = 0;
+    license
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => {}
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+/// Return the `LicenseRef-` references used by [`to_spdx()`] for `license`,
+/// with the licenses declared by Buildroot they stand for.
+pub fn license_refs(license: &str) -> BTreeMap<String, String> {
+    let mut refs = BTreeMap::new();
+    for choice in strip_comments(license)
+        .split(',')
+        .flat_map(|t| t.split(" or "))
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+    {
+        let id = spdx_identifier(choice);
+        if id.starts_with(LICENSE_REF) {
+            refs.entry(id).or_insert_with(|| choice.to_string());
+        }
+    }
+    refs
+}
+
+fn spdx_identifier(id: &str) -> String {
+    // The byte offsets of ASCII lowercase are the ones of the identifier.
+    if let Some(i) = id.to_ascii_lowercase().find(" with ") {
+        let license = spdx_identifier(id[..i].trim());
+        let exception = id[i + " with ".len()..].trim();
+        if !license.starts_with(LICENSE_REF) && !exception.is_empty() && is_valid(exception) {
+            return format!("{license} WITH {exception}");
+        }
+        return license_ref(id);
+    }
+    // The GNU licenses have dedicated identifiers for "only" and "or later".
+    let gnu = ["GPL-", "LGPL-", "AGPL-", "GFDL-"];
+    if gnu.iter().any(|p| id.starts_with(p)) && !id.ends_with("-only") {
+        if let Some(base) = id.strip_suffix('+') {
+            return format!("{base}-or-later");
+        }
+        if !id.ends_with("-or-later") {
+            return format!("{id}-only");
+        }
+    }
+    if is_valid(id) {
+        id.to_string()
+    } else {
+        license_ref(id)
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    id.chars()
+        .all(|c| c.is_ascii_alphanumeric() || ".-+".contains(c))
+}
+
+fn license_ref(id: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{LICENSE_REF}{}", id.trim_matches('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_license_refs() {
+        assert!(license_refs("MIT or unknown").is_empty());
+        assert_eq!(
+            license_refs("Public Domain (docs), GPL-2.0+ with Foo exception, MIT"),
+            BTreeMap::from([
+                (
+                    "LicenseRef-GPL-2.0--with-Foo-exception".to_string(),
+                    "GPL-2.0+ with Foo exception".to_string()
+                ),
+                (
+                    "LicenseRef-Public-Domain".to_string(),
+                    "Public Domain".to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn convert_to_spdx() {
+        assert_eq!(to_spdx("MIT"), "MIT");
+        assert_eq!(to_spdx("GPL-2.0+"), "GPL-2.0-or-later");
+        assert_eq!(
+            to_spdx("GPL-2.0 (programs), LGPL-2.1+ (library)"),
+            "GPL-2.0-only AND LGPL-2.1-or-later"
+        );
+        assert_eq!(
+            to_spdx("BSD-3-Clause or GPL-2.0, MIT, MIT"),
+            "(BSD-3-Clause OR GPL-2.0-only) AND MIT"
+        );
+        assert_eq!(to_spdx("Apache-2.0 or MIT"), "Apache-2.0 OR MIT");
+        assert_eq!(to_spdx("Public Domain"), "LicenseRef-Public-Domain");
+        assert_eq!(
+            to_spdx("GPL-3.0+ with GCC-exception-3.1"),
+            "GPL-3.0-or-later WITH GCC-exception-3.1"
+        );
+        assert_eq!(
+            to_spdx("GPL-2.0 with OpenSSL exception"),
+            "LicenseRef-GPL-2.0-with-OpenSSL-exception"
+        );
+        assert_eq!(
+            to_spdx("LGPL-2.1 WITH Linux-syscall-note or MIT"),
+            "LGPL-2.1-only WITH Linux-syscall-note OR MIT"
+        );
+        assert_eq!(
+            to_spdx("Public Domain with exceptions"),
+            "LicenseRef-Public-Domain-with-exceptions"
+        );
+        assert_eq!(to_spdx(""), "NOASSERTION");
+        assert_eq!(to_spdx("unknown"), "NOASSERTION");
+    }
+
+    #[test]
+    fn write_legal_manifest() {
+        let entry = LicenseEntry {
+            package: "foo".to_string(),
+            version: "1.2.3".to_string(),
+            license: "GPL-2.0+".to_string(),
+            spdx: "GPL-2.0-or-later".to_string(),
+            license_files: vec!["COPYING".to_string(), "README".to_string()],
+            source: "foo-1.2.3.tar.gz".to_string(),
+            site: "https://some.where/there".to_string(),
+            redistribute: true,
+            dependencies: BTreeMap::from([("host-bar".to_string(), "MIT".to_string())]),
+        };
+        let hidden = LicenseEntry {
+            package: "baz".to_string(),
+            redistribute: false,
+            dependencies: BTreeMap::new(),
+            ..entry.clone()
+        };
+        let mut output = vec![];
+        write_manifest(&[entry, hidden], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#""PACKAGE","VERSION","LICENSE","LICENSE FILES","SOURCE ARCHIVE","SOURCE SITE","DEPENDENCIES WITH LICENSES""#
+        );
+        assert_eq!(
+            lines[1],
+            r#""foo","1.2.3","GPL-2.0+","COPYING README","foo-1.2.3.tar.gz","https://some.where/there","host-bar [MIT]""#
+        );
+        assert_eq!(
+            lines[2],
+            r#""baz","1.2.3","GPL-2.0+","COPYING README","not saved","not saved","""#
+        );
+    }
+}
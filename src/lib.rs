@@ -8,10 +8,21 @@
 
 //! Provide helpers to handle a [Buildroot](https://buildroot.org) environment.
 
+pub mod board;
 pub mod builder;
 mod buildroot;
+pub mod cache;
 pub mod defconfig;
+pub mod legal;
+pub mod logs;
 pub mod mason;
+pub mod overrides;
 pub mod package;
+mod pool;
+pub mod sbom;
+pub mod shell;
+pub mod stats;
+mod timestamp;
+pub mod version;
 
 pub use buildroot::*;
@@ -0,0 +1,284 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for capturing the output of builds into log files.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use thiserror::Error;
+
+use super::timestamp::Timestamp;
+
+/// Default number of logs kept per build.
+pub const DEFAULT_KEEP: usize = 10;
+
+const LOG_EXTENSION: &str = ".log";
+const FAILED_EXTENSION: &str = ".failed.log";
+
+/// Errors reported when handling build logs.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No build log found in {0:?}")]
+    NoLog(PathBuf),
+}
+
+/// Stream of a build command output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Directory holding the most recent logs of a build.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildLogs {
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// Log file of a past build.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    /// Path to the log file.
+    pub path: PathBuf,
+    /// Whether the build failed.
+    pub failed: bool,
+}
+
+/// Log file of a running build, with timestamped lines.
+#[derive(Debug)]
+pub struct BuildLog {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl BuildLogs {
+    /// Create a new set of logs stored in `dir`, keeping the last `keep` ones.
+    pub fn new<P: AsRef<Path>>(dir: P, keep: usize) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Return the directory holding the logs.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Create a new log, removing the oldest ones.
+    pub fn create(&self) -> Result<BuildLog, Error> {
+        fs::create_dir_all(&self.dir)?;
+        let entries = self.list()?;
+        let count = (entries.len() + 1).saturating_sub(self.keep);
+        for entry in entries.iter().take(count) {
+            fs::remove_file(&entry.path)?;
+        }
+        let ts = Timestamp::now().compact();
+        for n in 0.. {
+            // Builds started within the same millisecond get a zero-padded
+            // suffix, which sorts after the plain name and in order.
+            let stem = match n {
+                0 => ts.clone(),
+                n => format!("{ts}_{n:03}"),
+            };
+            if self.dir.join(format!("{stem}{FAILED_EXTENSION}")).exists() {
+                continue;
+            }
+            let path = self.dir.join(format!("{stem}{LOG_EXTENSION}"));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(BuildLog {
+                        path,
+                        writer: Mutex::new(BufWriter::new(file)),
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        unreachable!("Exhausted log names")
+    }
+
+    /// List the available logs, from the oldest to the most recent.
+    pub fn list(&self) -> Result<Vec<LogEntry>, Error> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut entries = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter_map(|p| {
+                let name = p.file_name()?.to_str()?;
+                name.ends_with(LOG_EXTENSION).then(|| LogEntry {
+                    failed: name.ends_with(FAILED_EXTENSION),
+                    path: p.clone(),
+                })
+            })
+            .collect::<Vec<LogEntry>>();
+        // Names start with a timestamp, so they sort chronologically.
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Return the most recent log, or the most recent one of a failed build.
+    pub fn latest(&self, failed: bool) -> Result<LogEntry, Error> {
+        self.list()?
+            .into_iter()
+            .rfind(|e| e.failed || !failed)
+            .ok_or_else(|| Error::NoLog(self.dir.clone()))
+    }
+}
+
+impl LogEntry {
+    /// Read the contents of the log, or only its last `count` lines.
+    pub fn read(&self, count: Option<usize>) -> Result<String, Error> {
+        let text = fs::read_to_string(&self.path)?;
+        let Some(count) = count else {
+            return Ok(text);
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let start = lines.len().saturating_sub(count);
+        Ok(lines[start..].iter().map(|l| format!("{l}\n")).collect())
+    }
+}
+
+impl BuildLog {
+    /// Return the path to the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a line from `stream`, prefixed with the current time.
+    pub fn write_line(&self, stream: Stream, line: &str) -> Result<(), Error> {
+        let ts = Timestamp::now().rfc3339_millis();
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        match stream {
+            Stream::Stdout => writeln!(writer, "[{ts}] {line}")?,
+            Stream::Stderr => writeln!(writer, "[{ts}] [stderr] {line}")?,
+        }
+        Ok(())
+    }
+
+    /// Append the lines read from `reader` to the log, passing each one to
+    /// `on_line` too.
+    pub fn tee<R: Read, F: FnMut(&str)>(
+        &self,
+        reader: R,
+        stream: Stream,
+        mut on_line: F,
+    ) -> Result<(), Error> {
+        let mut reader = BufReader::new(reader);
+        let mut buf = vec![];
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\n', '\r']);
+            self.write_line(stream, line)?;
+            on_line(line);
+        }
+        Ok(())
+    }
+
+    /// Close the log, marking it as failed if the build did not succeed.
+    ///
+    /// Return the final path to the log file.
+    pub fn finish(self, success: bool) -> Result<PathBuf, Error> {
+        let mut writer = self.writer.into_inner().unwrap_or_else(|e| e.into_inner());
+        writer.flush()?;
+        if success {
+            return Ok(self.path);
+        }
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let stem = name.trim_end_matches(LOG_EXTENSION);
+        let path = self
+            .path
+            .with_file_name(format!("{stem}{FAILED_EXTENSION}"));
+        fs::rename(&self.path, &path)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = BuildLogs::new(dir.path().join("logs"), 2);
+        assert!(logs.list().unwrap().is_empty());
+        assert!(matches!(logs.latest(false), Err(Error::NoLog(_))));
+
+        let log = logs.create().unwrap();
+        let mut output = vec![];
+        log.tee(&b"hello\nworld"[..], Stream::Stdout, |l| {
+            output.push(l.to_string())
+        })
+        .unwrap();
+        log.write_line(Stream::Stderr, "oops").unwrap();
+        assert_eq!(output, ["hello", "world"]);
+        let failed = log.finish(false).unwrap();
+        assert!(failed.to_string_lossy().ends_with(".failed.log"));
+
+        let text = fs::read_to_string(&failed).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with('[') && lines[0].ends_with("] hello"));
+        assert!(lines[1].ends_with("] world"));
+        assert!(lines[2].ends_with("] [stderr] oops"));
+
+        let entry = logs.latest(true).unwrap();
+        assert_eq!(
+            entry,
+            LogEntry {
+                path: failed,
+                failed: true
+            }
+        );
+        assert_eq!(entry.read(Some(1)).unwrap(), format!("{}\n", lines[2]));
+
+        for _ in 0..2 {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            logs.create().unwrap().finish(true).unwrap();
+        }
+        let entries = logs.list().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| !e.failed));
+        assert!(matches!(logs.latest(true), Err(Error::NoLog(_))));
+        assert_eq!(logs.latest(false).unwrap(), entries[1]);
+    }
+
+    #[test]
+    fn create_logs_in_same_millisecond() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = BuildLogs::new(dir.path(), 20);
+        let paths: Vec<PathBuf> = (0..12)
+            .map(|i| {
+                let log = logs.create().unwrap();
+                log.write_line(Stream::Stdout, &i.to_string()).unwrap();
+                log.finish(i % 2 == 0).unwrap()
+            })
+            .collect();
+        let entries: Vec<PathBuf> = logs.list().unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(entries, paths);
+        for (i, path) in paths.iter().enumerate() {
+            let text = fs::read_to_string(path).unwrap();
+            assert!(text.ends_with(&format!("] {i}\n")));
+        }
+    }
+}
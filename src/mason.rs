@@ -14,7 +14,12 @@ use std::{
 };
 use thiserror::Error;
 
-use super::builder::{self, BuildStep, Builder};
+use super::{
+    builder::{self, BuildStep, Builder},
+    logs::{self, BuildLogs},
+    sbom::{self, Sbom, SbomFormat},
+    stats::{self, BuildTimes},
+};
 
 /// Errors reported when managing builds.
 #[derive(Debug, Error)]
@@ -23,12 +28,21 @@ pub enum Error {
     Builder(#[from] builder::Error),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("JSON serialization error: {0}")]
+    JsonSerialize(#[from] serde_json::Error),
+    #[error("Log error: {0}")]
+    Logs(#[from] logs::Error),
+    #[error("SBOM error: {0}")]
+    Sbom(#[from] sbom::Error),
+    #[error("Statistics error: {0}")]
+    Stats(#[from] stats::Error),
 }
 
 /// Manages builds.
 #[derive(Debug)]
 pub struct Mason {
     storage: PathBuf,
+    keep_logs: usize,
 }
 
 impl Mason {
@@ -36,9 +50,16 @@ impl Mason {
     pub fn new<P: AsRef<Path>>(storage: P) -> Self {
         Self {
             storage: storage.as_ref().to_path_buf(),
+            keep_logs: logs::DEFAULT_KEEP,
         }
     }
 
+    /// Set the number of logs kept per build definition.
+    pub fn keep_logs(&mut self, count: usize) -> &mut Self {
+        self.keep_logs = count;
+        self
+    }
+
     /// Add a new build definition, created from a `Builder`
     pub fn add_from_builder(&self, name: &str, builder: &Builder) -> Result<(), Error> {
         if !self.storage.exists() {
@@ -68,25 +89,47 @@ impl Mason {
         Ok(names)
     }
 
-    /// delete a build definition.
+    /// delete a build definition, with its logs.
     pub fn delete(&self, name: &str) -> Result<(), Error> {
         let path = self.build_definition_path(name);
         fs::remove_file(path)?;
+        let logs = self.logs(name);
+        if logs.dir().exists() {
+            fs::remove_dir_all(logs.dir())?;
+        }
         Ok(())
     }
 
+    /// Return the logs of the builds performed from a definition.
+    pub fn logs(&self, name: &str) -> BuildLogs {
+        BuildLogs::new(self.storage.join("logs").join(name), self.keep_logs)
+    }
+
     /// Perform a build from a definition.
     pub fn build(&self, name: &str, step: BuildStep) -> Result<(), Error> {
-        let builder = self.create_builder(name)?;
-        builder.run_step(step)?;
-        Ok(())
+        Ok(self.create_builder(name)?.run_step(step)?)
     }
 
     /// Build some specific targets of a build definition.
     pub fn execute<S: AsRef<str>>(&self, name: &str, targets: &[S]) -> Result<(), Error> {
+        Ok(self.create_builder(name)?.build_targets(targets)?)
+    }
+
+    /// Generate the SBOM of a build definition, as JSON in `format`.
+    ///
+    /// The `legal-info` target must have been built beforehand.
+    pub fn sbom(&self, name: &str, format: SbomFormat) -> Result<String, Error> {
         let builder = self.create_builder(name)?;
-        builder.build_targets(targets)?;
-        Ok(())
+        let sbom = Sbom::from_output(name, &builder.output)?;
+        let text = serde_json::to_string_pretty(&sbom.to_json(format))?;
+        Ok(text)
+    }
+
+    /// Return the time spent in each step of the last builds of a definition.
+    pub fn build_times(&self, name: &str) -> Result<BuildTimes, Error> {
+        let builder = self.create_builder(name)?;
+        let times = BuildTimes::from_output(&builder.output)?;
+        Ok(times)
     }
 
     ///  Print contents of a build definition
@@ -102,9 +145,11 @@ impl Mason {
         Ok(s)
     }
 
-    fn create_builder(&self, name: &str) -> Result<Builder, Error> {
+    /// Create a builder from a build definition.
+    pub fn create_builder(&self, name: &str) -> Result<Builder, Error> {
         let s = self.read_build_definition(name)?;
-        let b = Builder::from_toml(&s)?;
+        let mut b = Builder::from_toml(&s)?;
+        b.set_logs(self.logs(name));
         Ok(b)
     }
 
@@ -0,0 +1,224 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for building packages from local source directories.
+//!
+//! Buildroot reads `<PKG>_OVERRIDE_SRCDIR` variables from the file set by
+//! `BR2_PACKAGE_OVERRIDE_FILE`, `$(CONFIG_DIR)/local.mk` by default, and
+//! synchronizes the package from this directory instead of downloading it.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Suffix of the variables overriding the source directory of a package.
+const OVERRIDE_SUFFIX: &str = "_OVERRIDE_SRCDIR";
+
+/// Errors reported when handling source directory overrides.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid source directory: {0:?}")]
+    InvalidSourceDir(PathBuf),
+    #[error("Temporary file error: {0}")]
+    Persist(#[from] tempfile::PersistError),
+}
+
+/// File holding the source directory overrides, like `local.mk`.
+///
+/// Lines not overriding a source directory are kept as is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverrideFile {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl OverrideFile {
+    /// Read the overrides from `path`, which may not exist yet.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let lines = if path.exists() {
+            fs::read_to_string(&path)?
+                .lines()
+                .map(String::from)
+                .collect()
+        } else {
+            vec![]
+        };
+        Ok(Self { path, lines })
+    }
+
+    /// Return the path to the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the source directories, indexed by package name.
+    ///
+    /// The names are taken from `packages`, or their host variants, as the
+    /// variables do not tell dashes from underscores. Variables of other
+    /// packages are named with dashes.
+    pub fn overrides<'a, I>(&self, packages: I) -> BTreeMap<String, PathBuf>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let names: HashMap<String, String> = packages
+            .into_iter()
+            .flat_map(|p| [p.to_string(), format!("host-{p}")])
+            .map(|p| (override_variable(&p), p))
+            .collect();
+        self.lines
+            .iter()
+            .filter_map(|l| parse_override(l))
+            .map(|(v, p)| {
+                let name = names.get(v).cloned().unwrap_or_else(|| package_name(v));
+                (name, PathBuf::from(p))
+            })
+            .collect()
+    }
+
+    /// Set the source directory of `package`, which must be an existing
+    /// directory.
+    pub fn set<P: AsRef<Path>>(&mut self, package: &str, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let invalid = || Error::InvalidSourceDir(path.to_path_buf());
+        if !path.is_dir() {
+            return Err(invalid());
+        }
+        let path = fs::canonicalize(path)?;
+        // Make would expand variables and strip comments.
+        let value = path.to_str().ok_or_else(invalid)?;
+        if value.contains(['$', '#', '\n', '\\']) {
+            return Err(invalid());
+        }
+        let variable = override_variable(package);
+        let line = format!("{variable} = {value}");
+        self.remove(package);
+        self.lines.push(line);
+        Ok(())
+    }
+
+    /// Remove the source directory of `package`, returning whether it was
+    /// set.
+    pub fn remove(&mut self, package: &str) -> bool {
+        let variable = override_variable(package);
+        let count = self.lines.len();
+        self.lines
+            .retain(|l| parse_override(l).is_none_or(|(v, _)| v != variable));
+        self.lines.len() != count
+    }
+
+    /// Write the overrides, replacing the file atomically.
+    pub fn save(&self) -> Result<(), Error> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        for line in &self.lines {
+            writeln!(file, "{line}")?;
+        }
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            file.as_file().set_permissions(metadata.permissions())?;
+        }
+        file.persist(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Return the name of the variable overriding the source directory of
+/// `package`, like `HOST_FOO_BAR_OVERRIDE_SRCDIR` for `host-foo-bar`.
+pub fn override_variable(package: &str) -> String {
+    format!(
+        "{}{OVERRIDE_SUFFIX}",
+        package.to_uppercase().replace('-', "_")
+    )
+}
+
+fn package_name(variable: &str) -> String {
+    variable
+        .trim_end_matches(OVERRIDE_SUFFIX)
+        .to_lowercase()
+        .replace('_', "-")
+}
+
+fn parse_override(line: &str) -> Option<(&str, &str)> {
+    lazy_static! {
+        static ref OVERRIDE: Regex =
+            Regex::new(r"^\s*([A-Z0-9_]+_OVERRIDE_SRCDIR)\s*(?::=|\?=|=)\s*(.*?)\s*$").unwrap();
+    }
+
+    let caps = OVERRIDE.captures(line)?;
+    Some((caps.get(1)?.as_str(), caps.get(2)?.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_override_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local.mk");
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(
+            &path,
+            "# Local sources\nBAR_OVERRIDE_SRCDIR = /src/bar\nFOO_CONF_OPTS += --debug\n\
+             XDRIVER_XF86_VIDEO_FBDEV_OVERRIDE_SRCDIR = /src/fbdev\n",
+        )
+        .unwrap();
+
+        let mut file = OverrideFile::from_path(&path).unwrap();
+        assert_eq!(
+            file.overrides(["bar", "xdriver_xf86-video-fbdev"]),
+            BTreeMap::from([
+                ("bar".to_string(), PathBuf::from("/src/bar")),
+                (
+                    "xdriver_xf86-video-fbdev".to_string(),
+                    PathBuf::from("/src/fbdev")
+                ),
+            ])
+        );
+        assert!(file.overrides([]).contains_key("xdriver-xf86-video-fbdev"));
+        assert!(file.remove("xdriver_xf86-video-fbdev"));
+        file.set("host-foo-bar", &src).unwrap();
+        file.set("bar", &src).unwrap();
+        assert!(matches!(
+            file.set("baz", dir.path().join("missing")),
+            Err(Error::InvalidSourceDir(_))
+        ));
+        file.save().unwrap();
+
+        let src = fs::canonicalize(&src).unwrap();
+        let file = OverrideFile::from_path(&path).unwrap();
+        let overrides = file.overrides(["foo-bar", "bar"]);
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["host-foo-bar"], src);
+        assert_eq!(overrides["bar"], src);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# Local sources\nFOO_CONF_OPTS += --debug\n"));
+        assert!(text.contains(&format!(
+            "HOST_FOO_BAR_OVERRIDE_SRCDIR = {}\n",
+            src.display()
+        )));
+
+        let mut file = OverrideFile::from_path(&path).unwrap();
+        assert!(file.remove("bar"));
+        assert!(!file.remove("bar"));
+        assert_eq!(file.overrides([]).len(), 1);
+    }
+}
@@ -8,9 +8,11 @@
 
 //! Provide helpers for handling packages.
 
+use lazy_static::lazy_static;
 use regex::{Captures, Regex};
+use serde::Serialize;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Read};
@@ -24,6 +26,8 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Invalid filename: {0:?}")]
     InvalidFilename(OsString),
+    #[error("Invalid hash entry: {0}")]
+    InvalidHashEntry(String),
     #[error("Invalid variable: {0}")]
     InvalidVariable(String),
     #[error("Missing variable: {0}")]
@@ -31,52 +35,106 @@ pub enum Error {
 }
 
 /// Hold information about a package.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct PackageInfo {
     name: String,
-    properties: HashMap<&'static str, String>,
+    properties: BTreeMap<&'static str, String>,
+    infrastructures: Vec<String>,
 }
 
 impl PackageInfo {
     /// Collect package information from file at `path`.
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let file = File::open(&path)?;
-        let name = path
-            .as_ref()
-            .file_stem()
-            .map(|n| n.to_string_lossy())
-            .ok_or(Error::InvalidFilename(path.as_ref().as_os_str().into()))?;
+        let (name, file) = open(path.as_ref())?;
         Self::from_reader(&name, file)
     }
 
+    /// Collect package information from file at `path`, like
+    /// [`PackageInfo::from_path()`], but accepting packages without a
+    /// version, like virtual packages.
+    pub fn from_path_unversioned<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let (name, file) = open(path.as_ref())?;
+        Self::parse(&name, file)
+    }
+
     /// Collect package information from a readable object.
     fn from_reader<R: Read>(name: &str, reader: R) -> Result<Self, Error> {
+        let info = Self::parse(name, reader)?;
+        if !info.properties.contains_key("version") {
+            return Err(Error::MissingVariable("version".to_string()));
+        }
+        Ok(info)
+    }
+
+    fn parse<R: Read>(name: &str, reader: R) -> Result<Self, Error> {
+        lazy_static! {
+            static ref INFRASTRUCTURE: Regex =
+                Regex::new(r"^\s*\$\(eval\s+\$\(([a-z0-9-]*package)\)\)").unwrap();
+            static ref ASSIGNMENT: Regex =
+                Regex::new(r"^\s*([A-Z0-9_]+)\s*(\+=|\?=|:=|=)\s*(.*)$").unwrap();
+        }
+
         let stem = canonicalize(name);
-        let prop_names = ["version", "site", "source", "license", "dependencies"];
-        let vars_names: Vec<(&str, String)> = prop_names
+        let prop_names = [
+            "version",
+            "site",
+            "site_method",
+            "source",
+            "license",
+            "license_files",
+            "redistribute",
+            "dependencies",
+        ];
+        let mut vars_names: HashMap<String, &'static str> = prop_names
             .into_iter()
-            .map(|n| (n, format!("{}_{}", stem, n.to_uppercase())))
+            .map(|n| (format!("{}_{}", stem, n.to_uppercase()), n))
             .collect();
-        let mut properties = HashMap::new();
-        let reader = BufReader::new(reader);
-        for line in reader.lines() {
-            let line = line?;
-            for (prop_name, var_name) in &vars_names {
-                if line.starts_with(var_name) {
-                    let fields = line.split('=').collect::<Vec<&str>>();
-                    if fields.len() != 2 {
-                        return Err(Error::InvalidVariable(line));
+        vars_names.insert(format!("HOST_{stem}_DEPENDENCIES"), "host_dependencies");
+        let mut properties: BTreeMap<&'static str, String> = BTreeMap::new();
+        let mut infrastructures = vec![];
+        let mut reader = BufReader::new(reader).lines();
+        while let Some(line) = reader.next() {
+            let mut line = line?;
+            // Join continued lines.
+            while line.ends_with('\\') {
+                line.pop();
+                match reader.next() {
+                    Some(next) => line.push_str(&next?),
+                    None => break,
+                }
+            }
+            if let Some(caps) = INFRASTRUCTURE.captures(&line) {
+                infrastructures.push(caps[1].to_string());
+                continue;
+            }
+            let Some(caps) = ASSIGNMENT.captures(&line) else {
+                continue;
+            };
+            let Some(prop_name) = vars_names.get(&caps[1]) else {
+                continue;
+            };
+            let value = caps[3].split('#').next().unwrap_or_default();
+            let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+            match &caps[2] {
+                "+=" => {
+                    let current = properties.entry(prop_name).or_default();
+                    if !current.is_empty() && !value.is_empty() {
+                        current.push(' ');
                     }
-                    properties.insert(*prop_name, fields[1].trim().to_string());
+                    current.push_str(&value);
+                }
+                "?=" => {
+                    properties.entry(prop_name).or_insert(value);
+                }
+                _ => {
+                    properties.insert(prop_name, value);
                 }
             }
         }
-        if !properties.contains_key("version") {
-            return Err(Error::MissingVariable("version".to_string()));
-        }
         Ok(Self {
             name: name.to_string(),
             properties,
+            infrastructures,
         })
     }
 
@@ -86,14 +144,224 @@ impl PackageInfo {
     }
 
     /// Return the version of the package.
+    ///
+    /// It is empty for packages read without version.
     pub fn version(&self) -> &str {
-        &self.properties["version"]
+        self.properties.get("version").map_or("", String::as_str)
     }
 
     /// Return the properties of a package.
-    pub fn properties(&self) -> &HashMap<&'static str, String> {
+    pub fn properties(&self) -> &BTreeMap<&'static str, String> {
         &self.properties
     }
+
+    /// Return the value of the property `name`, with the references to the
+    /// other variables of the package expanded.
+    ///
+    /// References to variables which are not collected are kept as is.
+    pub fn property(&self, name: &str) -> Option<String> {
+        lazy_static! {
+            static ref REFERENCE: Regex = Regex::new(r"\$\(([A-Z0-9_]+)\)").unwrap();
+        }
+
+        let prefix = format!("{}_", canonicalize(&self.name));
+        let value = self.properties.get(name)?;
+        let expanded = REFERENCE.replace_all(value, |caps: &Captures| {
+            caps[1]
+                .strip_prefix(&prefix)
+                .map(|p| p.to_lowercase())
+                .filter(|p| p != name)
+                .and_then(|p| self.properties.get(p.as_str()))
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        Some(expanded.to_string())
+    }
+
+    /// Return the names of the packages the package depends on, with the
+    /// other variables of the package expanded.
+    ///
+    /// Conditional dependencies, using make functions or config symbols,
+    /// can not be resolved and are skipped.
+    pub fn dependencies(&self) -> Vec<String> {
+        self.package_list("dependencies")
+    }
+
+    /// Return the names of the packages the host variant of the package
+    /// depends on, like [`dependencies()`](Self::dependencies).
+    ///
+    /// Unless `HOST_<PKG>_DEPENDENCIES` is set, they are the host variants of
+    /// the dependencies of the target package, like in Buildroot.
+    pub fn host_dependencies(&self) -> Vec<String> {
+        if self.properties.contains_key("host_dependencies") {
+            return self.package_list("host_dependencies");
+        }
+        self.dependencies()
+            .into_iter()
+            .map(|d| match d.starts_with("host-") {
+                true => d,
+                false => format!("host-{d}"),
+            })
+            .collect()
+    }
+
+    /// Return the package names of the property `name`, skipping the
+    /// conditional ones.
+    fn package_list(&self, name: &str) -> Vec<String> {
+        self.property(name)
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter(|d| !d.contains(['$', '(', ')', ',']))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Return the package infrastructures used by the package, like
+    /// `autotools-package` or `host-cmake-package`.
+    pub fn infrastructures(&self) -> &[String] {
+        &self.infrastructures
+    }
+
+    /// Check if the package uses the infrastructure `name`, given with or
+    /// without its `host-` prefix and `-package` suffix (e.g. `cmake`).
+    pub fn uses_infrastructure(&self, name: &str) -> bool {
+        let short = |n: &str| {
+            let n = n.strip_prefix("host-").unwrap_or(n);
+            n.strip_suffix("-package").unwrap_or(n).to_string()
+        };
+        self.infrastructures
+            .iter()
+            .any(|i| i == name || short(i) == short(name))
+    }
+}
+
+/// Hold the configuration entry of a package, from its `Config.in` file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PackageConfig {
+    /// Name of the symbol, like `BR2_PACKAGE_FOO`.
+    pub symbol: String,
+    /// Prompt of the entry, if any.
+    pub prompt: Option<String>,
+    /// Help text of the entry.
+    pub help: String,
+}
+
+impl PackageConfig {
+    /// Collect the configuration entries of the `Config.in` file at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Self>, Error> {
+        let file = File::open(path)?;
+        Self::from_reader(file)
+    }
+
+    /// Collect the configuration entries from a readable object.
+    ///
+    /// Only `config` and `menuconfig` entries are handled: other Kconfig
+    /// statements end the current entry.
+    fn from_reader<R: Read>(reader: R) -> Result<Vec<Self>, Error> {
+        lazy_static! {
+            static ref ENTRY: Regex = Regex::new(r"^(?:menu)?config\s+(\w+)").unwrap();
+            static ref PROMPT: Regex =
+                Regex::new(r#"^(?:bool|tristate|string|int|hex|prompt)\s+"((?:[^"\\]|\\.)*)""#)
+                    .unwrap();
+            static ref HELP: Regex = Regex::new(r"^(?:help|---help---)\s*$").unwrap();
+        }
+
+        let mut entries: Vec<PackageConfig> = vec![];
+        let mut current: Option<PackageConfig> = None;
+        // Indentation of the help keyword, while reading a help text.
+        let mut help_indent: Option<usize> = None;
+        let reader = BufReader::new(reader);
+        for line in reader.lines() {
+            let line = line?.replace('\t', "        ");
+            let trimmed = line.trim();
+            let indent = line.len() - line.trim_start().len();
+            if let Some(help) = help_indent {
+                if trimmed.is_empty() || indent > help {
+                    if let Some(ref mut entry) = current {
+                        if !entry.help.is_empty() || !trimmed.is_empty() {
+                            entry.help.push_str(trimmed);
+                            entry.help.push('\n');
+                        }
+                    }
+                    continue;
+                }
+                help_indent = None;
+            }
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if let Some(caps) = ENTRY.captures(trimmed) {
+                entries.extend(current.take());
+                current = Some(PackageConfig {
+                    symbol: caps[1].to_string(),
+                    ..Default::default()
+                });
+            } else if let Some(ref mut entry) = current {
+                if let Some(caps) = PROMPT.captures(trimmed) {
+                    entry.prompt = Some(caps[1].replace("\\\"", "\""));
+                } else if HELP.is_match(trimmed) {
+                    help_indent = Some(indent);
+                } else if indent == 0 {
+                    entries.extend(current.take());
+                }
+            }
+        }
+        entries.extend(current);
+        for entry in entries.iter_mut() {
+            entry.help = entry.help.trim_end().to_string();
+        }
+        Ok(entries)
+    }
+}
+
+/// Hold an entry of the hash file of a package.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HashEntry {
+    /// Name of the hash algorithm, like `sha256`.
+    pub algorithm: String,
+    /// Value of the hash.
+    pub hash: String,
+    /// Name of the hashed file.
+    pub file: String,
+}
+
+impl HashEntry {
+    /// Collect the entries of the hash file at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Self>, Error> {
+        let file = File::open(path)?;
+        Self::from_reader(file)
+    }
+
+    /// Collect the entries of a hash file from a readable object.
+    fn from_reader<R: Read>(reader: R) -> Result<Vec<Self>, Error> {
+        let mut entries = vec![];
+        let reader = BufReader::new(reader);
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() != 3 {
+                return Err(Error::InvalidHashEntry(line.to_string()));
+            }
+            entries.push(HashEntry {
+                algorithm: fields[0].to_string(),
+                hash: fields[1].to_string(),
+                file: fields[2].to_string(),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Return the name of the symbol enabling the package `name`.
+pub fn package_symbol(name: &str) -> String {
+    match name.strip_prefix("host-") {
+        Some(name) => format!("BR2_PACKAGE_HOST_{}", canonicalize(name)),
+        None => format!("BR2_PACKAGE_{}", canonicalize(name)),
+    }
 }
 
 /// Set the version of the package in `path` to `version`.
@@ -115,6 +383,16 @@ fn replace_version<'t>(text: &'t str, name: &str, version: &str) -> Cow<'t, str>
     regex.replace(text, |caps: &Captures| format!("{}{}", &caps[1], version))
 }
 
+/// Open the file of a package, returning the name of the package too.
+fn open(path: &Path) -> Result<(String, File), Error> {
+    let file = File::open(path)?;
+    let name = path
+        .file_stem()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or(Error::InvalidFilename(path.as_os_str().into()))?;
+    Ok((name, file))
+}
+
 fn canonicalize(name: &str) -> String {
     name.to_uppercase().replace('-', "_")
 }
@@ -127,6 +405,40 @@ mod tests {
 # Comment
 FOO_VERSION    =   1.2.3
 FOO_SITE   =   https://some.where/there
+FOO_SITE_METHOD = git # Comment
+FOO_SOURCE = foo-$(FOO_VERSION).tar.gz $(BAR_VERSION)
+FOO_LICENSE = GPL-2.0+, \
+	BSD-3-Clause
+FOO_LICENSE_FILES = COPYING
+FOO_DEPENDENCIES = bar
+FOO_DEPENDENCIES += baz
+FOO_DEPENDENCIES ?= qux
+"##;
+    const PACKAGE_CMAKE: &str = r##"
+FOO_VERSION = 1.2.3
+
+$(eval $(cmake-package))
+$(eval $(host-cmake-package))
+"##;
+    const CONFIG_VALID: &str = r##"
+config BR2_PACKAGE_FOO
+	bool "foo"
+	depends on BR2_USE_MMU # fork()
+	select BR2_PACKAGE_BAR
+	help
+	  Foo is a \"fake\" package.
+
+	  https://some.where/there
+
+if BR2_PACKAGE_FOO
+
+config BR2_PACKAGE_FOO_EXTRA
+	bool "extra features"
+
+endif
+
+comment "foo needs a toolchain w/ threads"
+	depends on !BR2_TOOLCHAIN_HAS_THREADS
 "##;
     const PACKAGE_NO_VERSION: &str = r##"
 FOO_LICENSE = LGPL-2.0+
@@ -142,6 +454,83 @@ FOO_LICENSE = LGPL-2.0+
             pkg.properties().get("site").map(String::as_str),
             Some("https://some.where/there")
         );
+        assert_eq!(
+            pkg.property("source").as_deref(),
+            Some("foo-1.2.3.tar.gz $(BAR_VERSION)")
+        );
+        // FOO_SITE_METHOD does not overwrite FOO_SITE, and trailing comments
+        // are removed.
+        assert_eq!(pkg.property("site_method").as_deref(), Some("git"));
+        // Continued lines are joined.
+        assert_eq!(
+            pkg.property("license").as_deref(),
+            Some("GPL-2.0+, BSD-3-Clause")
+        );
+        // += appends to the value, while ?= only sets undefined variables.
+        assert_eq!(pkg.property("dependencies").as_deref(), Some("bar baz"));
+        let pkg =
+            PackageInfo::from_reader("foo", "FOO_VERSION ?= 1.0\nFOO_SITE += /src\n".as_bytes())
+                .unwrap();
+        assert_eq!(pkg.version(), "1.0");
+        assert_eq!(pkg.property("site").as_deref(), Some("/src"));
+    }
+
+    #[test]
+    fn parse_package_dependencies() {
+        let text = "FOO_VERSION = 1.0\nFOO_DEPENDENCIES = bar $(if $(BR2_PACKAGE_BAZ),baz) \
+                    $(FOO_EXTRA)\nFOO_DEPENDENCIES += qux\n";
+        let pkg = PackageInfo::from_reader("foo", text.as_bytes()).unwrap();
+        assert_eq!(pkg.dependencies(), ["bar", "qux"]);
+        assert_eq!(pkg.host_dependencies(), ["host-bar", "host-qux"]);
+        let text = "FOO_VERSION = 1.0\nFOO_DEPENDENCIES = bar\nHOST_FOO_DEPENDENCIES = host-baz\n";
+        let pkg = PackageInfo::from_reader("foo", text.as_bytes()).unwrap();
+        assert_eq!(pkg.host_dependencies(), ["host-baz"]);
+        let res = PackageInfo::from_reader("foo", "FOO_DEPENDENCIES = bar\n".as_bytes());
+        assert!(matches!(res, Err(Error::MissingVariable(v)) if v == "version"));
+        let pkg = PackageInfo::parse("foo", "FOO_DEPENDENCIES = bar\n".as_bytes()).unwrap();
+        assert_eq!(pkg.version(), "");
+    }
+
+    #[test]
+    fn parse_package_infrastructures() {
+        let pkg = PackageInfo::from_reader("foo", PACKAGE_CMAKE.as_bytes()).unwrap();
+        assert_eq!(
+            pkg.infrastructures(),
+            ["cmake-package", "host-cmake-package"]
+        );
+        assert!(pkg.uses_infrastructure("cmake"));
+        assert!(pkg.uses_infrastructure("host-cmake-package"));
+        assert!(!pkg.uses_infrastructure("autotools"));
+        let pkg = PackageInfo::from_reader("foo", PACKAGE_VALID.as_bytes()).unwrap();
+        assert!(pkg.infrastructures().is_empty());
+    }
+
+    #[test]
+    fn parse_package_config() {
+        let entries = PackageConfig::from_reader(CONFIG_VALID.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].symbol, package_symbol("foo"));
+        assert_eq!(entries[0].prompt.as_deref(), Some("foo"));
+        assert_eq!(
+            entries[0].help,
+            "Foo is a \\\"fake\\\" package.\n\nhttps://some.where/there"
+        );
+        assert_eq!(entries[1].symbol, "BR2_PACKAGE_FOO_EXTRA");
+        assert_eq!(entries[1].prompt.as_deref(), Some("extra features"));
+        assert!(entries[1].help.is_empty());
+        assert_eq!(package_symbol("host-foo-bar"), "BR2_PACKAGE_HOST_FOO_BAR");
+    }
+
+    #[test]
+    fn parse_hash_file() {
+        let text =
+            "# Locally computed\nsha256  0123abcd  foo-1.2.3.tar.gz\n\nsha256  4567ef  COPYING\n";
+        let entries = HashEntry::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].algorithm, "sha256");
+        assert_eq!(entries[0].hash, "0123abcd");
+        assert_eq!(entries[1].file, "COPYING");
+        assert!(HashEntry::from_reader("sha256 0123abcd\n".as_bytes()).is_err());
     }
 
     #[test]
@@ -0,0 +1,559 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for generating a software bill of materials (SBOM) from
+//! the output directory of a build.
+//!
+//! The SBOM is built from local files only: the manifest generated by
+//! `make legal-info`, the hash files it copies along with the sources, and
+//! the list of files installed by each package.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+use super::{legal, package::HashEntry, timestamp::Timestamp};
+
+/// Namespace of the UUIDs generated for documents (the URL namespace of RFC 4122).
+const UUID_NAMESPACE: [u8; 16] = [
+    0x6b, 0xa7, 0xb8, 0x11, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8,
+];
+
+/// Errors reported when generating a SBOM.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Invalid format: {0}")]
+    InvalidFormat(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Missing legal information (run \"make legal-info\"): {0:?}")]
+    MissingLegalInfo(PathBuf),
+    #[error("Package error: {0}")]
+    Package(#[from] crate::package::Error),
+}
+
+/// Format of a SBOM.
+#[derive(Debug, Clone, Copy)]
+pub enum SbomFormat {
+    /// SPDX 2.3, as JSON
+    Spdx,
+    /// CycloneDX 1.5, as JSON
+    CycloneDx,
+}
+
+impl FromStr for SbomFormat {
+    type Err = self::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "spdx" => Ok(SbomFormat::Spdx),
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            _ => Err(Error::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for SbomFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SbomFormat::Spdx => write!(f, "spdx"),
+            SbomFormat::CycloneDx => write!(f, "cyclonedx"),
+        }
+    }
+}
+
+/// Entry of the `manifest.csv` file generated by `make legal-info`.
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    #[serde(rename = "PACKAGE")]
+    package: String,
+    #[serde(rename = "VERSION")]
+    version: String,
+    #[serde(rename = "LICENSE")]
+    license: String,
+    #[serde(rename = "SOURCE ARCHIVE")]
+    source: String,
+    #[serde(rename = "SOURCE SITE")]
+    site: String,
+}
+
+/// File installed in the target by a package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SbomFile {
+    /// Path of the file, relative to the root of the target.
+    pub path: String,
+    /// SHA1 checksum of the file.
+    pub sha1: String,
+}
+
+/// Package of a SBOM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SbomPackage {
+    /// Name of the package.
+    pub name: String,
+    /// Version of the package.
+    pub version: String,
+    /// License of the package, as a SPDX expression.
+    pub license: String,
+    /// License of the package, as declared by Buildroot.
+    pub declared_license: String,
+    /// Location of the source archive, if known.
+    pub download_location: Option<String>,
+    /// Checksums of the source archive.
+    pub checksums: Vec<HashEntry>,
+    /// Files installed in the target by the package.
+    pub files: Vec<SbomFile>,
+}
+
+/// Software bill of materials of a build.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sbom {
+    /// Name of the build.
+    pub name: String,
+    /// Packages of the build, sorted by name.
+    pub packages: Vec<SbomPackage>,
+}
+
+impl Sbom {
+    /// Collect the SBOM of the build named `name`, from its output directory.
+    ///
+    /// `make legal-info` must have been run beforehand.
+    pub fn from_output<P: AsRef<Path>>(name: &str, output: P) -> Result<Self, Error> {
+        let output = output.as_ref();
+        let legal_info = output.join("legal-info");
+        let manifest = legal_info.join("manifest.csv");
+        if !manifest.exists() {
+            return Err(Error::MissingLegalInfo(manifest));
+        }
+        let mut files = read_file_list(output)?;
+        let mut packages = vec![];
+        for entry in csv::Reader::from_path(manifest)?.deserialize() {
+            let entry: ManifestEntry = entry?;
+            let sources = legal_info
+                .join("sources")
+                .join(format!("{}-{}", entry.package, entry.version));
+            let checksums = read_checksums(&sources)?
+                .into_iter()
+                .filter(|h| h.file == entry.source)
+                .collect();
+            let download_location = ["http://", "https://", "ftp://"]
+                .iter()
+                .any(|p| entry.site.starts_with(p))
+                .then(|| format!("{}/{}", entry.site.trim_end_matches('/'), entry.source));
+            packages.push(SbomPackage {
+                version: entry.version,
+                license: legal::to_spdx(&entry.license),
+                declared_license: entry.license,
+                download_location,
+                checksums,
+                files: files.remove(&entry.package).unwrap_or_default(),
+                name: entry.package,
+            });
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self {
+            name: name.to_string(),
+            packages,
+        })
+    }
+
+    /// Return the SBOM as a JSON document, in `format`.
+    pub fn to_json(&self, format: SbomFormat) -> Value {
+        let created = timestamp();
+        match format {
+            SbomFormat::Spdx => self.to_spdx(&created),
+            SbomFormat::CycloneDx => self.to_cyclonedx(&created),
+        }
+    }
+
+    fn to_spdx(&self, created: &str) -> Value {
+        let namespace = format!(
+            "https://spdx.org/spdxdocs/{}-{}",
+            spdx_id(&self.name),
+            uuid(&format!("{}@{}", self.name, created))
+        );
+        let mut packages = vec![];
+        let mut files = vec![];
+        let mut relationships = vec![];
+        let mut license_refs = BTreeMap::new();
+        for package in &self.packages {
+            for (id, license) in legal::license_refs(&package.declared_license) {
+                license_refs.entry(id).or_insert(license);
+            }
+            let id = format!("SPDXRef-Package-{}", spdx_id(&package.name));
+            relationships.push(json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": id,
+            }));
+            for file in &package.files {
+                let file_id = format!("SPDXRef-File-{}", files.len() + 1);
+                files.push(json!({
+                    "SPDXID": file_id,
+                    "fileName": format!("./{}", file.path),
+                    "checksums": [{"algorithm": "SHA1", "checksumValue": file.sha1}],
+                }));
+                relationships.push(json!({
+                    "spdxElementId": id,
+                    "relationshipType": "CONTAINS",
+                    "relatedSpdxElement": file_id,
+                }));
+            }
+            let checksums: Vec<Value> = package
+                .checksums
+                .iter()
+                .filter_map(|h| {
+                    let algorithm = checksum_algorithm(&h.algorithm)?;
+                    Some(json!({"algorithm": algorithm.replace('-', ""), "checksumValue": h.hash}))
+                })
+                .collect();
+            let mut spdx_package = json!({
+                "SPDXID": id,
+                "name": package.name,
+                "versionInfo": package.version,
+                "downloadLocation": package.download_location.as_deref().unwrap_or("NOASSERTION"),
+                "filesAnalyzed": !package.files.is_empty(),
+                "checksums": checksums,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": package.license,
+                "copyrightText": "NOASSERTION",
+            });
+            // Required when the files of the package are listed.
+            if !package.files.is_empty() {
+                spdx_package["packageVerificationCode"] =
+                    json!({"packageVerificationCodeValue": verification_code(&package.files)});
+            }
+            packages.push(spdx_package);
+        }
+        let extracted: Vec<Value> = license_refs
+            .into_iter()
+            .map(|(id, license)| {
+                json!({
+                    "licenseId": id,
+                    "name": license,
+                    "extractedText": license,
+                })
+            })
+            .collect();
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.name,
+            "documentNamespace": namespace,
+            "creationInfo": {
+                "created": created,
+                "creators": [format!("Tool: br2-utils-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "files": files,
+            "hasExtractedLicensingInfos": extracted,
+            "relationships": relationships,
+        })
+    }
+
+    fn to_cyclonedx(&self, created: &str) -> Value {
+        let uuid = uuid(&format!("{}@{}", self.name, created));
+        let components: Vec<Value> = self
+            .packages
+            .iter()
+            .map(|package| {
+                let mut component = json!({
+                    "type": "library",
+                    "bom-ref": format!("{}@{}", package.name, package.version),
+                    "name": package.name,
+                    "version": package.version,
+                });
+                if package.license != "NOASSERTION" {
+                    component["licenses"] = json!([{"expression": package.license}]);
+                }
+                let hashes: Vec<Value> = package
+                    .checksums
+                    .iter()
+                    .filter_map(|h| {
+                        let algorithm = checksum_algorithm(&h.algorithm)?;
+                        Some(json!({"alg": algorithm, "content": h.hash}))
+                    })
+                    .collect();
+                if !hashes.is_empty() {
+                    component["hashes"] = json!(hashes);
+                }
+                if let Some(ref url) = package.download_location {
+                    component["externalReferences"] = json!([{"type": "distribution", "url": url}]);
+                }
+                if !package.files.is_empty() {
+                    let files: Vec<Value> = package
+                        .files
+                        .iter()
+                        .map(|f| {
+                            json!({
+                                "type": "file",
+                                "name": format!("/{}", f.path),
+                                "hashes": [{"alg": "SHA-1", "content": f.sha1}],
+                            })
+                        })
+                        .collect();
+                    component["components"] = json!(files);
+                }
+                component
+            })
+            .collect();
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", uuid),
+            "version": 1,
+            "metadata": {
+                "timestamp": created,
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "br2-utils",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": "firmware",
+                    "bom-ref": self.name,
+                    "name": self.name,
+                },
+            },
+            "components": components,
+        })
+    }
+}
+
+/// Read the files installed in the target by each package, from
+/// `build/packages-file-list.txt`, skipping the ones removed since.
+///
+/// Symbolic links are skipped too, as they may point to files of the host,
+/// like `/etc/resolv.conf`.
+fn read_file_list(output: &Path) -> Result<BTreeMap<String, Vec<SbomFile>>, Error> {
+    let mut files: BTreeMap<String, Vec<SbomFile>> = BTreeMap::new();
+    let path = output.join("build").join("packages-file-list.txt");
+    if !path.exists() {
+        return Ok(files);
+    }
+    let target = output.join("target");
+    for line in fs::read_to_string(path)?.lines() {
+        let Some((package, file)) = line.split_once(',') else {
+            continue;
+        };
+        let file = file.trim_start_matches("./");
+        let path = target.join(file);
+        if !fs::symlink_metadata(&path).is_ok_and(|m| m.is_file()) {
+            continue;
+        }
+        let Ok(contents) = fs::read(path) else {
+            continue;
+        };
+        files
+            .entry(package.to_string())
+            .or_default()
+            .push(SbomFile {
+                path: file.to_string(),
+                sha1: sha1_smol::Sha1::from(contents).digest().to_string(),
+            });
+    }
+    Ok(files)
+}
+
+/// Read the hash files copied by `make legal-info` in the directory of the
+/// sources of a package.
+fn read_checksums(dir: &Path) -> Result<Vec<HashEntry>, Error> {
+    let mut checksums = vec![];
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(checksums);
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "hash") {
+            checksums.extend(HashEntry::from_path(path)?);
+        }
+    }
+    Ok(checksums)
+}
+
+/// Return the CycloneDX name of a hash algorithm used in Buildroot hash files.
+fn checksum_algorithm(name: &str) -> Option<&'static str> {
+    match name {
+        "md5" => Some("MD5"),
+        "sha1" => Some("SHA-1"),
+        "sha256" => Some("SHA-256"),
+        "sha384" => Some("SHA-384"),
+        "sha512" => Some("SHA-512"),
+        _ => None,
+    }
+}
+
+fn spdx_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Generate a name-based UUID (version 5) for `name`.
+fn uuid(name: &str) -> String {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(&UUID_NAMESPACE);
+    hasher.update(name.as_bytes());
+    let mut bytes = hasher.digest().bytes();
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Compute the SPDX verification code of the files of a package, i.e. the
+/// SHA-1 of their sorted SHA-1 checksums.
+fn verification_code(files: &[SbomFile]) -> String {
+    let mut checksums: Vec<&str> = files.iter().map(|f| f.sha1.as_str()).collect();
+    checksums.sort_unstable();
+    sha1_smol::Sha1::from(checksums.concat())
+        .digest()
+        .to_string()
+}
+
+/// Return the current time, or the one from `SOURCE_DATE_EPOCH` for
+/// reproducible documents, in RFC 3339 format.
+fn timestamp() -> String {
+    match std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    {
+        Some(secs) => Timestamp::from_epoch(Duration::from_secs(secs)).rfc3339(),
+        None => Timestamp::now().rfc3339(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    fn mock_output<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let legal_info = path.join("legal-info");
+        let sources = legal_info.join("sources").join("foo-1.2.3");
+        fs::create_dir_all(&sources)?;
+        let manifest = r#""PACKAGE","VERSION","LICENSE","LICENSE FILES","SOURCE ARCHIVE","SOURCE SITE","DEPENDENCIES WITH LICENSES"
+"foo","1.2.3","GPL-2.0+ with GCC-exception-3.1, Public Domain (docs)","COPYING","foo-1.2.3.tar.gz","https://some.where/there",""
+"bar","4.5.6","unknown","","not saved","not saved",""
+"#;
+        fs::write(legal_info.join("manifest.csv"), manifest)?;
+        let hashes = "sha256  0123abcd  foo-1.2.3.tar.gz\nsha256  4567ef  COPYING\n";
+        fs::write(sources.join("foo.hash"), hashes)?;
+        fs::create_dir_all(path.join("build"))?;
+        let files = "foo,./usr/bin/foo\nfoo,./usr/bin/removed\nbar,./etc/bar.conf\n\
+                     bar,./etc/resolv.conf\nbar,./etc\n";
+        fs::write(path.join("build").join("packages-file-list.txt"), files)?;
+        fs::create_dir_all(path.join("target").join("usr").join("bin"))?;
+        fs::create_dir_all(path.join("target").join("etc"))?;
+        fs::write(
+            path.join("target").join("usr").join("bin").join("foo"),
+            "foo",
+        )?;
+        fs::write(path.join("target").join("etc").join("bar.conf"), "")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            "/etc/resolv.conf",
+            path.join("target").join("etc").join("resolv.conf"),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn collect_sbom() {
+        let path = Builder::new().prefix("br2-utils-test").tempdir().unwrap();
+        assert!(Sbom::from_output("acme", &path).is_err());
+        mock_output(&path).unwrap();
+        let sbom = Sbom::from_output("acme", &path).unwrap();
+        let names: Vec<&str> = sbom.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["bar", "foo"]);
+        let foo = &sbom.packages[1];
+        assert_eq!(
+            foo.license,
+            "GPL-2.0-or-later WITH GCC-exception-3.1 AND LicenseRef-Public-Domain"
+        );
+        assert_eq!(
+            foo.download_location.as_deref(),
+            Some("https://some.where/there/foo-1.2.3.tar.gz")
+        );
+        assert_eq!(foo.checksums.len(), 1);
+        assert_eq!(foo.files.len(), 1);
+        assert_eq!(
+            foo.files[0].sha1,
+            "0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33"
+        );
+        assert!(sbom.packages[0].download_location.is_none());
+        // Symbolic links and directories are not hashed.
+        let files: Vec<&str> = sbom.packages[0]
+            .files
+            .iter()
+            .map(|f| f.path.as_str())
+            .collect();
+        assert_eq!(files, ["etc/bar.conf"]);
+
+        let spdx = sbom.to_json(SbomFormat::Spdx);
+        assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+        assert_eq!(spdx["packages"][1]["licenseDeclared"], foo.license);
+        assert_eq!(
+            spdx["hasExtractedLicensingInfos"],
+            json!([{
+                "licenseId": "LicenseRef-Public-Domain",
+                "name": "Public Domain",
+                "extractedText": "Public Domain",
+            }])
+        );
+        assert_eq!(spdx["packages"][1]["filesAnalyzed"], true);
+        // The verification code of a single file is the SHA-1 of its SHA-1.
+        assert_eq!(
+            spdx["packages"][1]["packageVerificationCode"]["packageVerificationCodeValue"],
+            sha1_smol::Sha1::from(&foo.files[0].sha1)
+                .digest()
+                .to_string()
+        );
+        assert_eq!(spdx["packages"][1]["checksums"][0]["algorithm"], "SHA256");
+        assert_eq!(spdx["files"].as_array().unwrap().len(), 2);
+        let cyclonedx = sbom.to_json(SbomFormat::CycloneDx);
+        assert_eq!(cyclonedx["specVersion"], "1.5");
+        assert_eq!(cyclonedx["components"][1]["hashes"][0]["alg"], "SHA-256");
+        assert!(cyclonedx["components"][0].get("licenses").is_none());
+    }
+
+    #[test]
+    fn generate_identifiers() {
+        let id = uuid("acme");
+        assert_eq!(id, uuid("acme"));
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "5");
+        assert_eq!(spdx_id("baz_qux"), "baz-qux");
+    }
+}
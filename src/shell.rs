@@ -0,0 +1,86 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for printing commands as POSIX shell scripts.
+
+use std::{path::Path, process::Command};
+
+/// Quote `s` so that it is interpreted literally by a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Format `cmd` as a POSIX shell command line, preceded by the environment
+/// variables it sets.
+pub fn shell_command(cmd: &Command) -> String {
+    let envs = cmd.get_envs().filter_map(|(name, value)| {
+        let value = value?.to_string_lossy();
+        Some(format!(
+            "{}={}",
+            name.to_string_lossy(),
+            shell_quote(&value)
+        ))
+    });
+    let args = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| shell_quote(&a.to_string_lossy()));
+    envs.chain(args).collect::<Vec<String>>().join(" ")
+}
+
+/// Format the POSIX shell commands writing `text` to the file at `path`,
+/// creating its parent directory, using a here-document.
+pub fn write_file_command(path: &Path, text: &str) -> String {
+    let mut delimiter = "EOF".to_string();
+    while text.lines().any(|l| l == delimiter) {
+        delimiter.push('_');
+    }
+    let mut script = String::new();
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        script.push_str(&format!(
+            "mkdir -p {}\n",
+            shell_quote(&dir.to_string_lossy())
+        ));
+    }
+    script.push_str(&format!(
+        "cat > {} <<'{delimiter}'\n",
+        shell_quote(&path.to_string_lossy())
+    ));
+    script.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        script.push('\n');
+    }
+    script.push_str(&delimiter);
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_words() {
+        assert_eq!(shell_quote("BR2_JLEVEL=4"), "BR2_JLEVEL=4");
+        assert_eq!(shell_quote("my output"), "'my output'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn format_write_file() {
+        let script = write_file_command(Path::new("/tmp/my output/x"), "A=y\nEOF\n");
+        assert_eq!(
+            script,
+            "mkdir -p '/tmp/my output'\ncat > '/tmp/my output/x' <<'EOF_'\nA=y\nEOF\nEOF_"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for analyzing the time spent building each package.
+//!
+//! Buildroot records the start and end of each step of the packages in
+//! `build/build-time.log`, in the output directory, with lines like
+//! `1709294400.123456789:start:build               : busybox`.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Errors reported when analyzing build times.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Invalid build time entry: {0}")]
+    InvalidEntry(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Missing build time log (build first): {0:?}")]
+    MissingLog(PathBuf),
+}
+
+/// Time spent in a step of a package.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StepTime {
+    /// Name of the package.
+    pub package: String,
+    /// Name of the step, like `configure` or `install-target`.
+    pub step: String,
+    /// Start of the step, in seconds since the Unix epoch.
+    pub start: f64,
+    /// End of the step, in seconds since the Unix epoch.
+    pub end: f64,
+}
+
+impl StepTime {
+    /// Return the duration of the step, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+/// Time spent building a package, in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PackageTime {
+    /// Name of the package.
+    pub package: String,
+    /// Time spent in all the steps.
+    pub duration: f64,
+    /// Time spent in each step.
+    pub steps: BTreeMap<String, f64>,
+}
+
+/// Difference of the time spent building a package between two builds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TimeDiff {
+    /// Name of the package.
+    pub package: String,
+    /// Time spent in the first build, if the package was built.
+    pub before: Option<f64>,
+    /// Time spent in the second build, if the package was built.
+    pub after: Option<f64>,
+}
+
+impl TimeDiff {
+    /// Return the difference of time, in seconds.
+    pub fn delta(&self) -> f64 {
+        self.after.unwrap_or_default() - self.before.unwrap_or_default()
+    }
+}
+
+/// Times of the steps of a build.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildTimes {
+    steps: Vec<StepTime>,
+}
+
+impl BuildTimes {
+    /// Read the times of the build performed in `output`.
+    pub fn from_output<P: AsRef<Path>>(output: P) -> Result<Self, Error> {
+        let path = output.as_ref().join("build").join("build-time.log");
+        if !path.exists() {
+            return Err(Error::MissingLog(path));
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the contents of a `build-time.log` file.
+    ///
+    /// Steps started but not finished, like in an interrupted build, are
+    /// ignored. The log is appended to by each run of make, so only the last
+    /// run of a step of a package, like after `make <pkg>-rebuild`, is kept.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut started: HashMap<(String, String), f64> = HashMap::new();
+        let mut finished: HashMap<(String, String), StepTime> = HashMap::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let fields: Vec<&str> = line.splitn(4, ':').map(str::trim).collect();
+            let [time, kind, step, package] = fields[..] else {
+                return Err(Error::InvalidEntry(line.to_string()));
+            };
+            let time = time
+                .parse::<f64>()
+                .map_err(|_| Error::InvalidEntry(line.to_string()))?;
+            let key = (package.to_string(), step.to_string());
+            match kind {
+                "start" => {
+                    started.insert(key, time);
+                }
+                "end" => {
+                    if let Some(start) = started.remove(&key) {
+                        let step = StepTime {
+                            package: key.0.clone(),
+                            step: key.1.clone(),
+                            start,
+                            end: time,
+                        };
+                        finished.insert(key, step);
+                    }
+                }
+                _ => return Err(Error::InvalidEntry(line.to_string())),
+            }
+        }
+        let mut steps: Vec<StepTime> = finished.into_values().collect();
+        steps.sort_by(|a, b| {
+            a.start
+                .total_cmp(&b.start)
+                .then_with(|| (&a.package, &a.step).cmp(&(&b.package, &b.step)))
+        });
+        Ok(Self { steps })
+    }
+
+    /// Return the steps, in the order they started.
+    pub fn steps(&self) -> &[StepTime] {
+        &self.steps
+    }
+
+    /// Return the time elapsed between the start of the first step and the
+    /// end of the last one, in seconds.
+    pub fn total(&self) -> f64 {
+        let start = self.steps.iter().map(|s| s.start).reduce(f64::min);
+        let end = self.steps.iter().map(|s| s.end).reduce(f64::max);
+        match (start, end) {
+            (Some(start), Some(end)) => end - start,
+            _ => 0.0,
+        }
+    }
+
+    /// Return the time spent building each package, the slowest first.
+    pub fn packages(&self) -> Vec<PackageTime> {
+        let mut packages: BTreeMap<&str, PackageTime> = BTreeMap::new();
+        for step in &self.steps {
+            let entry = packages
+                .entry(&step.package)
+                .or_insert_with(|| PackageTime {
+                    package: step.package.clone(),
+                    duration: 0.0,
+                    steps: BTreeMap::new(),
+                });
+            entry.duration += step.duration();
+            *entry.steps.entry(step.step.clone()).or_default() += step.duration();
+        }
+        let mut packages: Vec<PackageTime> = packages.into_values().collect();
+        packages.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+        packages
+    }
+
+    /// Return the steps, the slowest first.
+    pub fn slowest_steps(&self) -> Vec<&StepTime> {
+        let mut steps: Vec<&StepTime> = self.steps.iter().collect();
+        steps.sort_by(|a, b| b.duration().total_cmp(&a.duration()));
+        steps
+    }
+
+    /// Compare the time spent building each package with `other`, the
+    /// largest increase first.
+    pub fn compare(&self, other: &BuildTimes) -> Vec<TimeDiff> {
+        let mut diffs: BTreeMap<String, TimeDiff> = BTreeMap::new();
+        for (times, is_after) in [(self, false), (other, true)] {
+            for package in times.packages() {
+                let diff = diffs
+                    .entry(package.package.clone())
+                    .or_insert_with(|| TimeDiff {
+                        package: package.package,
+                        before: None,
+                        after: None,
+                    });
+                if is_after {
+                    diff.after = Some(package.duration);
+                } else {
+                    diff.before = Some(package.duration);
+                }
+            }
+        }
+        let mut diffs: Vec<TimeDiff> = diffs.into_values().collect();
+        diffs.sort_by(|a, b| b.delta().total_cmp(&a.delta()));
+        diffs
+    }
+
+    /// Write the times of the steps as CSV.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["package", "step", "start", "end", "duration"])?;
+        for step in &self.steps {
+            writer.write_record([
+                step.package.as_str(),
+                step.step.as_str(),
+                &format!("{:.3}", step.start),
+                &format!("{:.3}", step.end),
+                &format!("{:.3}", step.duration()),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Convert the times of the steps into the trace event format of
+    /// Chrome, viewable in `chrome://tracing` or Perfetto.
+    ///
+    /// Steps running at the same time, in parallel builds, are put on
+    /// separate threads.
+    pub fn to_chrome_trace(&self) -> Value {
+        let origin = self.steps.first().map(|s| s.start).unwrap_or_default();
+        let micros = |secs: f64| ((secs - origin) * 1e6).round() as u64;
+        let mut lanes: Vec<f64> = vec![];
+        let events: Vec<Value> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let lane = match lanes.iter().position(|&end| end <= step.start) {
+                    Some(i) => i,
+                    None => {
+                        lanes.push(0.0);
+                        lanes.len() - 1
+                    }
+                };
+                lanes[lane] = step.end;
+                json!({
+                    "name": format!("{} {}", step.package, step.step),
+                    "cat": step.step,
+                    "ph": "X",
+                    "ts": micros(step.start),
+                    // The clock may go backwards, like after an NTP update.
+                    "dur": micros(step.end).saturating_sub(micros(step.start)),
+                    "pid": 1,
+                    "tid": lane + 1,
+                    "args": { "package": step.package },
+                })
+            })
+            .collect();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+/// Write the comparison of two builds as CSV.
+pub fn write_comparison_csv<W: Write>(diffs: &[TimeDiff], writer: W) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["package", "before", "after", "delta"])?;
+    let secs = |d: Option<f64>| d.map(|d| format!("{d:.3}")).unwrap_or_default();
+    for diff in diffs {
+        writer.write_record([
+            diff.package.as_str(),
+            &secs(diff.before),
+            &secs(diff.after),
+            &format!("{:.3}", diff.delta()),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+100.0:start:extract             : busybox
+101.5:end  :extract             : busybox
+100.5:start:build               : host-skeleton
+102.0:end  :build               : host-skeleton
+101.5:start:build               : busybox
+110.0:end  :build               : busybox
+110.0:start:install-target      : busybox
+";
+
+    #[test]
+    fn parse_build_times() {
+        let times = BuildTimes::parse(LOG).unwrap();
+        assert_eq!(times.steps().len(), 3);
+        assert_eq!(times.total(), 10.0);
+        let packages = times.packages();
+        assert_eq!(packages[0].package, "busybox");
+        assert_eq!(packages[0].duration, 10.0);
+        assert_eq!(packages[0].steps["build"], 8.5);
+        assert_eq!(packages[1].package, "host-skeleton");
+        let slowest = times.slowest_steps();
+        assert_eq!(
+            (slowest[0].package.as_str(), slowest[0].step.as_str()),
+            ("busybox", "build")
+        );
+
+        assert!(matches!(
+            BuildTimes::parse("100.0:start:build"),
+            Err(Error::InvalidEntry(_))
+        ));
+        assert!(matches!(
+            BuildTimes::parse("now:start:build : foo"),
+            Err(Error::InvalidEntry(_))
+        ));
+    }
+
+    #[test]
+    fn parse_repeated_steps() {
+        let log = format!(
+            "{LOG}\
+             200.0:start:build               : busybox\n\
+             203.0:end  :build               : busybox\n\
+             203.0:start:install-target      : busybox\n"
+        );
+        let times = BuildTimes::parse(&log).unwrap();
+        assert_eq!(times.steps().len(), 3);
+        let packages = times.packages();
+        assert_eq!(packages[0].package, "busybox");
+        assert_eq!(packages[0].steps["build"], 3.0);
+        assert_eq!(packages[0].duration, 4.5);
+        assert_eq!(times.steps()[2].start, 200.0);
+    }
+
+    #[test]
+    fn compare_build_times() {
+        let before = BuildTimes::parse(LOG).unwrap();
+        let after = BuildTimes::parse(
+            "200.0:start:build : busybox\n215.0:end:build : busybox\n\
+             200.0:start:build : zlib\n201.0:end:build : zlib\n",
+        )
+        .unwrap();
+        let diffs = before.compare(&after);
+        assert_eq!(diffs.len(), 3);
+        assert_eq!(diffs[0].package, "busybox");
+        assert_eq!(diffs[0].delta(), 5.0);
+        assert_eq!(diffs[1].package, "zlib");
+        assert_eq!(diffs[1].before, None);
+        assert_eq!(diffs[2].package, "host-skeleton");
+        assert_eq!(diffs[2].after, None);
+
+        let mut output = vec![];
+        write_comparison_csv(&diffs, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().nth(2), Some("zlib,,1.000,1.000"));
+    }
+
+    #[test]
+    fn export_build_times() {
+        let times = BuildTimes::parse(LOG).unwrap();
+        let mut output = vec![];
+        times.write_csv(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.lines().nth(1),
+            Some("busybox,extract,100.000,101.500,1.500")
+        );
+
+        let trace = times.to_chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["ts"], 0);
+        assert_eq!(events[0]["dur"], 1_500_000);
+        // host-skeleton overlaps with the extraction of busybox.
+        assert_eq!(events[1]["tid"], 2);
+        assert_eq!(events[2]["tid"], 1);
+
+        let times = BuildTimes::parse("100.0:start:build : foo\n99.0:end:build : foo\n").unwrap();
+        assert_eq!(times.to_chrome_trace()["traceEvents"][0]["dur"], 0);
+    }
+}
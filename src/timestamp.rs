@@ -0,0 +1,92 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for formatting timestamps, in UTC.
+
+use std::time::{Duration, SystemTime};
+
+/// Date and time, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Timestamp {
+    year: i64,
+    month: i64,
+    day: i64,
+    secs: u64,
+    millis: u32,
+}
+
+impl Timestamp {
+    /// Return the current date and time.
+    pub(crate) fn now() -> Self {
+        let elapsed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::from_epoch(elapsed)
+    }
+
+    /// Return the date and time at `elapsed` since the Unix epoch.
+    pub(crate) fn from_epoch(elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs();
+        // Convert days since the epoch to a civil date (proleptic Gregorian).
+        let days = (secs / 86400) as i64 + 719468;
+        let era = days.div_euclid(146097);
+        let doe = days.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            year: yoe + era * 400 + i64::from(month <= 2),
+            month,
+            day,
+            secs: secs % 86400,
+            millis: elapsed.subsec_millis(),
+        }
+    }
+
+    /// Format as RFC 3339, like `2024-03-01T12:00:00Z`.
+    pub(crate) fn rfc3339(&self) -> String {
+        format!("{}T{}Z", self.date("-"), self.time(":"))
+    }
+
+    /// Format as RFC 3339 with milliseconds, like `2024-03-01T12:00:00.123Z`.
+    pub(crate) fn rfc3339_millis(&self) -> String {
+        format!("{}T{}.{:03}Z", self.date("-"), self.time(":"), self.millis)
+    }
+
+    /// Format without separators, for use in file names, like
+    /// `20240301T120000.123Z`.
+    pub(crate) fn compact(&self) -> String {
+        format!("{}T{}.{:03}Z", self.date(""), self.time(""), self.millis)
+    }
+
+    fn date(&self, sep: &str) -> String {
+        format!("{:04}{sep}{:02}{sep}{:02}", self.year, self.month, self.day)
+    }
+
+    fn time(&self, sep: &str) -> String {
+        let (h, m, s) = (self.secs / 3600, self.secs % 3600 / 60, self.secs % 60);
+        format!("{h:02}{sep}{m:02}{sep}{s:02}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamp() {
+        let at = |secs| Timestamp::from_epoch(Duration::from_secs(secs));
+        assert_eq!(at(0).rfc3339(), "1970-01-01T00:00:00Z");
+        assert_eq!(at(1709294400).rfc3339(), "2024-03-01T12:00:00Z");
+        let ts = Timestamp::from_epoch(Duration::from_millis(1709294400123));
+        assert_eq!(ts.rfc3339_millis(), "2024-03-01T12:00:00.123Z");
+        assert_eq!(ts.compact(), "20240301T120000.123Z");
+    }
+}
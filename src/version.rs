@@ -0,0 +1,283 @@
+//
+// This file is part of br2-utils
+//
+// SPDX-FileCopyrightText: © 2023 Eric Le Bihan <eric.le.bihan.dev@free.fr>
+//
+// SPDX-License-Identifier: MIT
+//
+
+//! Provide helpers for identifying the version of a Buildroot tree.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Errors reported when identifying the version of a Buildroot tree.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+    #[error("Missing version in {0:?}")]
+    MissingVersion(PathBuf),
+}
+
+/// Information about the Git checkout of a Buildroot tree.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GitInfo {
+    /// Identifier of the checked out commit.
+    pub commit: String,
+    /// Name of the checked out branch, if any.
+    pub branch: Option<String>,
+    /// Name of a tag pointing to the checked out commit, if any.
+    pub tag: Option<String>,
+}
+
+impl GitInfo {
+    /// Read information from the Git repository of the tree at `path`,
+    /// without invoking `git`.
+    ///
+    /// Only lightweight tags and tags peeled in `packed-refs` are detected.
+    pub fn from_tree<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        let git_path = path.join(".git");
+        let git_dir = if git_path.is_file() {
+            // Worktree or submodule: ".git" contains "gitdir: <path>".
+            let contents = fs::read_to_string(&git_path).ok()?;
+            path.join(contents.trim().strip_prefix("gitdir:")?.trim())
+        } else {
+            git_path
+        };
+        let common_dir = fs::read_to_string(git_dir.join("commondir"))
+            .map(|d| git_dir.join(d.trim()))
+            .unwrap_or_else(|_| git_dir.clone());
+        let packed_refs = fs::read_to_string(common_dir.join("packed-refs")).unwrap_or_default();
+
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let head = head.trim();
+        let (commit, branch) = match head.strip_prefix("ref:") {
+            Some(name) => {
+                let name = name.trim();
+                let commit = resolve_ref(&common_dir, &packed_refs, name)?;
+                let branch = name.strip_prefix("refs/heads/").map(String::from);
+                (commit, branch)
+            }
+            None => (head.to_string(), None),
+        };
+        let tag = find_tag(&common_dir, &packed_refs, &commit);
+        Some(Self {
+            commit,
+            branch,
+            tag,
+        })
+    }
+
+    /// Describe the checkout like `git describe --always` would for an exact match.
+    pub fn describe(&self) -> String {
+        match self.tag {
+            Some(ref tag) => tag.clone(),
+            None => self.commit.chars().take(12).collect(),
+        }
+    }
+}
+
+fn resolve_ref(git_dir: &Path, packed_refs: &str, name: &str) -> Option<String> {
+    if let Ok(commit) = fs::read_to_string(git_dir.join(name)) {
+        return Some(commit.trim().to_string());
+    }
+    packed_refs.lines().find_map(|l| {
+        let (commit, r) = l.split_once(' ')?;
+        (r.trim() == name).then(|| commit.to_string())
+    })
+}
+
+fn find_tag(git_dir: &Path, packed_refs: &str, commit: &str) -> Option<String> {
+    let tags_dir = git_dir.join("refs").join("tags");
+    let loose = walkdir::WalkDir::new(&tags_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .find(|e| {
+            fs::read_to_string(e.path())
+                .map(|c| c.trim() == commit)
+                .unwrap_or(false)
+        })
+        .and_then(|e| {
+            e.path()
+                .strip_prefix(&tags_dir)
+                .ok()
+                .map(|p| p.to_string_lossy().to_string())
+        });
+    if loose.is_some() {
+        return loose;
+    }
+    // In "packed-refs", an annotated tag is followed by "^<commit>".
+    let mut last_tag = None;
+    for line in packed_refs.lines() {
+        if let Some(peeled) = line.strip_prefix('^') {
+            if peeled.trim() == commit && last_tag.is_some() {
+                return last_tag;
+            }
+            continue;
+        }
+        last_tag = None;
+        if let Some((id, name)) = line.split_once(' ') {
+            if let Some(tag) = name.trim().strip_prefix("refs/tags/") {
+                if id == commit {
+                    return Some(tag.to_string());
+                }
+                last_tag = Some(tag.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Version of a Buildroot tree, like `2024.02.3`, `2024.02.x` or `2024.05-rc1`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BuildrootVersion {
+    /// Year of the release.
+    pub year: u16,
+    /// Month of the release.
+    pub month: u8,
+    /// Number of the maintenance release, if any.
+    pub patch: Option<u16>,
+    /// Whether the version designates a maintenance branch, like `2024.02.x`.
+    pub branch: bool,
+    /// Suffix of the version, like `rc1` or `git`.
+    pub suffix: Option<String>,
+    /// Information about the Git checkout, if the tree is one.
+    pub git: Option<GitInfo>,
+}
+
+impl BuildrootVersion {
+    /// Identify the version of the Buildroot tree at `path`, from the
+    /// `BR2_VERSION` variable of its main `Makefile`.
+    pub fn from_tree<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        lazy_static! {
+            static ref BR2_VERSION: Regex =
+                Regex::new(r"(?m)^export\s+BR2_VERSION\s*:=\s*(\S+)").unwrap();
+        }
+
+        let makefile = path.as_ref().join("Makefile");
+        let contents = fs::read_to_string(&makefile)?;
+        let caps = BR2_VERSION
+            .captures(&contents)
+            .ok_or(Error::MissingVersion(makefile))?;
+        let mut version = caps[1].parse::<BuildrootVersion>()?;
+        version.git = GitInfo::from_tree(path);
+        Ok(version)
+    }
+
+    /// Check if the version belongs to a long term support branch.
+    ///
+    /// Since 2017.02, the February releases are maintained for a year or more.
+    pub fn is_lts(&self) -> bool {
+        self.month == 2 && self.year >= 2017
+    }
+
+    /// Check if the version is a development snapshot.
+    pub fn is_development(&self) -> bool {
+        self.suffix.as_deref() == Some("git")
+    }
+}
+
+impl FromStr for BuildrootVersion {
+    type Err = self::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        lazy_static! {
+            static ref VERSION: Regex =
+                Regex::new(r"^(\d{4})\.(\d{2})(?:\.(\d+|x))?(?:-(\w+))?$").unwrap();
+        }
+
+        let invalid = || Error::InvalidVersion(s.to_string());
+        let caps = VERSION.captures(s).ok_or_else(invalid)?;
+        let month = caps[2].parse::<u8>().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) {
+            return Err(invalid());
+        }
+        let branch = caps.get(3).is_some_and(|p| p.as_str() == "x");
+        let patch = match caps.get(3) {
+            Some(p) if !branch => Some(p.as_str().parse().map_err(|_| invalid())?),
+            _ => None,
+        };
+        Ok(Self {
+            year: caps[1].parse().map_err(|_| invalid())?,
+            month,
+            patch,
+            branch,
+            suffix: caps.get(4).map(|s| s.as_str().to_string()),
+            git: None,
+        })
+    }
+}
+
+impl std::fmt::Display for BuildrootVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{:02}", self.year, self.month)?;
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        } else if self.branch {
+            write!(f, ".x")?;
+        }
+        if let Some(ref suffix) = self.suffix {
+            write!(f, "-{}", suffix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn parse_version() {
+        let version = "2024.02.3".parse::<BuildrootVersion>().unwrap();
+        assert_eq!((version.year, version.month), (2024, 2));
+        assert_eq!(version.patch, Some(3));
+        assert!(version.is_lts());
+        assert_eq!(version.to_string(), "2024.02.3");
+        let version = "2024.05-rc1".parse::<BuildrootVersion>().unwrap();
+        assert_eq!(version.suffix.as_deref(), Some("rc1"));
+        assert!(!version.is_lts());
+        assert_eq!(version.to_string(), "2024.05-rc1");
+        let version = "2024.02.x".parse::<BuildrootVersion>().unwrap();
+        assert!(version.branch && version.is_lts());
+        assert_eq!(version.to_string(), "2024.02.x");
+        assert!("2024.02.y".parse::<BuildrootVersion>().is_err());
+        assert!("2024.13".parse::<BuildrootVersion>().is_err());
+    }
+
+    #[test]
+    fn version_from_tree() {
+        let path = Builder::new().prefix("br2-utils-test").tempdir().unwrap();
+        let makefile = "# Set and export the version string\nexport BR2_VERSION := 2023.02.9-git\n";
+        fs::write(path.path().join("Makefile"), makefile).unwrap();
+        let git_dir = path.path().join(".git");
+        fs::create_dir_all(git_dir.join("refs").join("heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/2023.02.x\n").unwrap();
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let packed_refs = format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{c} refs/heads/2023.02.x\nfedcba {t}\n^{c}\n",
+            c = commit,
+            t = "refs/tags/2023.02.9"
+        );
+        fs::write(git_dir.join("packed-refs"), packed_refs).unwrap();
+        let version = BuildrootVersion::from_tree(&path).unwrap();
+        assert!(version.is_development());
+        let git = version.git.unwrap();
+        assert_eq!(git.commit, commit);
+        assert_eq!(git.branch.as_deref(), Some("2023.02.x"));
+        assert_eq!(git.describe(), "2023.02.9");
+    }
+}